@@ -0,0 +1,45 @@
+//! Steps per second of the befunge93 interpreter with the default settings, on a
+//! loop that counts up in a cell with `g` and `p` and prints each number.
+//!
+//! This is a baseline to measure changes to `step` against. Reworking the
+//! history lookups in the step path made no measurable difference, so those
+//! changes were dropped.
+//!
+//! The `history` group runs the same loop with the position, `g`, `p` and
+//! changed cell tracking all on, then all off (synth-404). Median of 5 runs:
+//!
+//! | on               | off              |
+//! |------------------|------------------|
+//! | 413 µs (24M/s)   | 332 µs (30M/s)   |
+
+use befunge_editor::bench::Runner;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+const STEPS: usize = 10_000;
+
+const COUNTER: &str = "\
+>02g1+:02p:.55+,v
+^               <
+";
+
+fn step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    group.throughput(Throughput::Elements(STEPS as u64));
+    let mut runner = Runner::new(COUNTER);
+    group.bench_function("counter", |b| b.iter(|| runner.run(STEPS)));
+    group.finish();
+}
+
+fn history(c: &mut Criterion) {
+    let mut group = c.benchmark_group("history");
+    group.throughput(Throughput::Elements(STEPS as u64));
+    for on in [true, false] {
+        let mut runner = Runner::new(COUNTER).with_history(on);
+        let name = if on { "on" } else { "off" };
+        group.bench_function(name, |b| b.iter(|| runner.run(STEPS)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, step, history);
+criterion_main!(benches);
@@ -1,5 +1,5 @@
 use coarsetime::{Duration, Instant};
-use egui::ahash::HashMap;
+use egui::ahash::{AHasher, HashMap, HashSet};
 use egui::containers::menu::SubMenuButton;
 use egui::emath::TSTransform;
 use egui::scroll_area::ScrollBarVisibility;
@@ -11,21 +11,33 @@ use egui::{
 use egui_material_icons::icons;
 use include_dir::{Dir, include_dir};
 use rfd::FileHandle;
+use std::collections::VecDeque;
 use std::future::Future;
+use std::hash::{Hash, Hasher};
 use std::ops::Range;
 use std::sync::mpsc::{Receiver, Sender, channel};
 
-use egui::{Color32, Pos2, Rect, Scene, Sense, Stroke, TextureHandle, Ui, Vec2, pos2};
+use egui::{Color32, Pos2, Rect, Scene, Sense, Stroke, TextureHandle, Ui, Vec2};
 
 use crate::befunge::{
     Befunge, BefungeVersion, BefungeVersionDiscriminants, Direction, FungeSpaceTrait,
-    GraphicalEvent, Position, StepStatus, Value, get_color_of_bf_op,
+    GraphicalEvent, Graphics, MAX_SEED, Position, StateSnapshot, StepStatus, TraceEntry, Value,
+    bounding_box, control_name, get_color_of_bf_op, op_name, random_seed,
 };
 use crate::{befunge93, befunge93mini};
 
 static PRESETS: Dir = include_dir!("./bf_programs");
+/// Width and height of the field in standard Befunge-93
+const STANDARD_SIZE: (i64, i64) = (80, 25);
 static CURSOR_COLOR: Color32 = Color32::from_rgb(110, 200, 255);
+static WATCHPOINT_COLOR: Color32 = Color32::from_rgb(220, 60, 220);
+static IP_COLOR: Color32 = Color32::PURPLE;
+static BREAKPOINT_COLOR: Color32 = Color32::GREEN;
 static PROFILE_EACH_CHAR: bool = false;
+/// Most ops listed in the op counts, busiest first
+const MAX_OP_COUNTS_SHOWN: usize = 16;
+/// Output longer than this (in bytes) is only laid out line by line as it's scrolled into view
+const LARGE_OUTPUT_LENGTH: usize = 16 * 1024;
 macro_rules! icon {
     ($icon:expr, $text:expr) => {
         const_format::concatcp!($icon, "\u{2009}", $text)
@@ -38,6 +50,8 @@ const SHORTCUT_SAVE: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND
 const SHORTCUT_SAVE_AS: KeyboardShortcut =
     KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::S);
 const SHORTCUT_RELOAD_FILE: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::R);
+const SHORTCUT_COPY_PROGRAM: KeyboardShortcut =
+    KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::C);
 
 const SHORTCUT_UNDO: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
 const SHORTCUT_REDO: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Y);
@@ -111,6 +125,8 @@ impl FungeSpaceTrait for FungeSpace {
     }
 
     fn program_size(&self) -> (i64, i64) {
+        // never smaller than 10x10, so an empty program still has room on the grid.
+        // These cells aren't stored any differently, spaces there are removed too
         let (mut width, mut height) = (10, 10);
         for (x, y) in self.map.keys() {
             if *y > height {
@@ -129,20 +145,42 @@ impl FungeSpaceTrait for FungeSpace {
 }
 
 impl FungeSpace {
-    pub fn new_from_string(input: &str) -> FungeSpace {
+    /// Parses a program, expanding tabs to stops every `tab_width` columns if given,
+    /// otherwise keeping each tab as a single cell
+    pub fn new_from_string(input: &str, tab_width: Option<usize>) -> FungeSpace {
+        let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+        // accept \r\n, old mac style lone \r, and any mix of them
+        let input = input.replace("\r\n", "\n").replace('\r', "\n");
+
         let mut map = FungeSpace::default();
         for (y, line) in input.lines().enumerate() {
-            for (x, char) in line.chars().enumerate() {
+            let mut x = 0;
+            for char in line.chars() {
+                if let Some(width) = tab_width
+                    && char == '\t'
+                {
+                    let width = width.max(1);
+                    x = (x / width + 1) * width;
+                    continue;
+                }
                 map.map.insert(
                     (x.try_into().unwrap(), y.try_into().unwrap()),
                     char as Value,
                 );
+                x += 1;
             }
         }
         map
     }
 }
 
+/// What a single step did, so the UI can react to it
+struct StepResult {
+    status: StepStatus,
+    /// Whether stepping should stop for now
+    stop: bool,
+}
+
 #[derive(Clone)]
 enum Mode {
     Editing {
@@ -151,6 +189,7 @@ enum Mode {
         cursor_state: CursorState,
         fungespace: FungeSpace,
         stdin: String,
+        breakpoints: HashSet<Position>,
     },
     Playing {
         snapshot: (FungeSpace, String),
@@ -161,10 +200,95 @@ enum Mode {
         running: bool,
         follow: bool,
         speed: u8,
-        error_state: Option<&'static str>,
+        error_state: Option<(String, Position)>,
+        loop_detector: LoopDetector,
+        step_history: VecDeque<StateSnapshot>,
     },
 }
 
+/// Cheap heuristic for spotting programs that are stuck, by remembering a
+/// fingerprint of each recent (position, direction, stack) state.
+/// Also notices the IP wandering around outside of the program
+#[derive(Clone, Default)]
+struct LoopDetector {
+    recent: VecDeque<u64>,
+    counts: HashMap<u64, u32>,
+    detected: Option<Position>,
+    dismissed: bool,
+    /// Bounding box of the program's cells, found the first time it's needed
+    bounds: Option<Option<(Position, Position)>>,
+    steps_outside: usize,
+    wandering: Option<Position>,
+    wander_dismissed: bool,
+}
+
+impl LoopDetector {
+    fn observe(&mut self, bf_state: &BefungeVersion, window: usize) {
+        if self.detected.is_some() || self.dismissed {
+            return;
+        }
+
+        let mut hasher = AHasher::default();
+        bf_state.cursor_position().hash(&mut hasher);
+        bf_state.cursor_direction().hash(&mut hasher);
+        bf_state.string_mode().hash(&mut hasher);
+        bf_state.stack_len().hash(&mut hasher);
+        for depth in 0..4 {
+            bf_state.stack_peek(depth).hash(&mut hasher);
+        }
+        let fingerprint = hasher.finish();
+
+        let count = self.counts.entry(fingerprint).or_default();
+        *count += 1;
+        if *count > 1 {
+            self.detected = Some(bf_state.cursor_position());
+        }
+
+        self.recent.push_back(fingerprint);
+        while self.recent.len() > window {
+            let Some(old) = self.recent.pop_front() else {
+                break;
+            };
+            if let Some(count) = self.counts.get_mut(&old) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&old);
+                }
+            }
+        }
+    }
+
+    /// Returns true when the IP has just been outside the program for more than `limit` steps
+    fn observe_bounds(&mut self, bf_state: &BefungeVersion, limit: usize) -> bool {
+        if self.wandering.is_some() || self.wander_dismissed {
+            return false;
+        }
+
+        let pos = bf_state.cursor_position();
+        let bounds = *self
+            .bounds
+            .get_or_insert_with(|| bounding_box(bf_state.cells()));
+        if bounds.is_some_and(|bounds| intersects(bounds, pos)) {
+            self.steps_outside = 0;
+            return false;
+        }
+
+        self.steps_outside += 1;
+        if self.steps_outside > limit {
+            // `p` may have put cells out here since the bounds were found
+            let bounds = bounding_box(bf_state.cells());
+            self.bounds = Some(bounds);
+            if bounds.is_some_and(|bounds| intersects(bounds, pos)) {
+                self.steps_outside = 0;
+                return false;
+            }
+            self.wandering = Some(pos);
+            return true;
+        }
+        false
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize, PartialEq)]
 pub enum InvalidOperationBehaviour {
     Reflect,
@@ -172,6 +296,33 @@ pub enum InvalidOperationBehaviour {
     Ignore,
 }
 
+/// Line ending used when saving, loading accepts any of them
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    /// Converts text serialized with `\n` line endings to use this line ending
+    fn apply(self, text: String) -> String {
+        match self {
+            LineEnding::Lf => text,
+            LineEnding::CrLf => text.replace('\n', "\r\n"),
+            LineEnding::Cr => text.replace('\n', "\r"),
+        }
+    }
+}
+
+/// What `+`, `-` and `*` do when the result doesn't fit in a cell
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy)]
+pub enum Overflow {
+    Wrap,
+    Saturate,
+    Error,
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Settings {
     pub pos_history: (bool, [u8; 3]),
@@ -179,11 +330,67 @@ pub struct Settings {
     pub put_history: (bool, [u8; 3]),
     pub skip_spaces: bool,
     pub render_unicode: bool,
+    /// Show control characters in the field by name, like LF, instead of a number
+    pub control_names: bool,
     pub display_debug_info: bool,
     pub run_until_breakpoint: bool,
     pub invalid_operation_behaviour: InvalidOperationBehaviour,
     pub befunge_version: BefungeVersionDiscriminants,
     pub non_blocking_input: bool,
+    pub record_trace: bool,
+    pub loop_detection: (bool, usize),
+    /// Warn when the IP has been outside the program's cells for this many steps
+    pub wander_detection: (bool, usize),
+    pub pause_on_wander: bool,
+    pub save_modified_field: bool,
+    pub output_auto_scroll: bool,
+    /// Show output in a fixed width font, laid out like a terminal would
+    pub terminal_output: bool,
+    /// Count how many times each op runs, shown in the side panel
+    pub count_ops: bool,
+    /// Columns and rows of a fixed size console for terminal style output to wrap and scroll in
+    pub console_size: (bool, (usize, usize)),
+    pub output_limit: (bool, usize),
+    /// Most values the stack can hold before the run halts
+    pub stack_limit: (bool, usize),
+    pub dot_trailing_space: bool,
+    pub tab_width: usize,
+    pub tab_inserts_spaces: bool,
+    pub paste_follows_direction: bool,
+    pub strict_stack: bool,
+    /// Make `~` and `,` read and write UTF-8 bytes rather than whole characters
+    pub input_bytes: bool,
+    pub strict_graphics: bool,
+    /// Round `/` and `%` towards negative infinity, instead of towards zero
+    pub floored_division: bool,
+    pub last_op_flash: (bool, [u8; 3]),
+    /// Outline cells whose value differs from the field the run started with
+    pub changed_cells: (bool, [u8; 3]),
+    pub trail_duration_ms: u64,
+    pub graphics_scale: (bool, f32),
+    pub graphics_grid: bool,
+    /// How many times a second the canvas is redrawn, whatever the step speed
+    pub graphics_fps: u32,
+    pub pause_on_output: bool,
+    pub locked_seed: (bool, u64),
+    pub line_ending: LineEnding,
+    /// Expand tabs in loaded programs to stops this many columns apart, rather than
+    /// keeping each one as a cell
+    pub expand_tabs: (bool, usize),
+    /// Values on the stack when a run starts, bottom first and separated by spaces
+    pub initial_stack: String,
+    pub initial_direction: Direction,
+    pub initial_position: Position,
+    /// Read the stack from the top down when showing it as text
+    pub stack_text_top_first: bool,
+    /// Only show this many values from the top of the stack
+    pub compact_stack: (bool, usize),
+    /// How many steps the "Run" button takes
+    pub run_steps: usize,
+    pub overflow: Overflow,
+    pub warn_oversized: bool,
+    /// Programs saved from the editor, as (name, program)
+    pub user_presets: Vec<(String, String)>,
 }
 
 impl Default for Settings {
@@ -196,13 +403,75 @@ impl Default for Settings {
             display_debug_info: false,
             run_until_breakpoint: false,
             render_unicode: true,
-            invalid_operation_behaviour: InvalidOperationBehaviour::Halt,
+            control_names: false,
+            invalid_operation_behaviour: InvalidOperationBehaviour::Ignore,
             befunge_version: BefungeVersionDiscriminants::Befunge93,
             non_blocking_input: false,
+            record_trace: false,
+            loop_detection: (true, 64),
+            wander_detection: (true, 1000),
+            pause_on_wander: false,
+            save_modified_field: false,
+            output_auto_scroll: true,
+            terminal_output: false,
+            count_ops: false,
+            console_size: (false, (80, 25)),
+            output_limit: (true, 100_000),
+            stack_limit: (true, 1_000_000),
+            dot_trailing_space: true,
+            tab_width: 4,
+            tab_inserts_spaces: false,
+            paste_follows_direction: false,
+            strict_stack: false,
+            input_bytes: false,
+            strict_graphics: false,
+            floored_division: false,
+            last_op_flash: (false, [255, 220, 0]),
+            changed_cells: (false, [255, 120, 40]),
+            trail_duration_ms: DEFAULT_TRAIL_DURATION_MS,
+            graphics_scale: (true, 2.0),
+            graphics_grid: false,
+            graphics_fps: 60,
+            pause_on_output: false,
+            locked_seed: (false, 0),
+            line_ending: LineEnding::Lf,
+            expand_tabs: (false, 8),
+            initial_stack: String::new(),
+            initial_direction: Direction::East,
+            initial_position: (0, 0),
+            stack_text_top_first: true,
+            compact_stack: (false, 16),
+            run_steps: 100,
+            overflow: Overflow::Wrap,
+            warn_oversized: false,
+            user_presets: Vec::new(),
         }
     }
 }
 
+impl Settings {
+    /// Tab stop width for loading programs, if tabs should be expanded
+    fn loaded_tab_width(&self) -> Option<usize> {
+        self.expand_tabs.0.then_some(self.expand_tabs.1)
+    }
+
+    /// Puts everything back to the defaults, returning what it was before.
+    /// Saved programs aren't settings, so they're kept
+    fn reset(&mut self) -> Settings {
+        let mut previous = std::mem::take(self);
+        self.user_presets = std::mem::take(&mut previous.user_presets);
+        previous
+    }
+
+    /// Brings back settings from before a [`Settings::reset`], keeping any
+    /// programs saved since
+    fn undo_reset(&mut self, previous: Settings) {
+        let user_presets = std::mem::take(&mut self.user_presets);
+        *self = previous;
+        self.user_presets = user_presets;
+    }
+}
+
 struct CharRenderer {
     glyph_uv_position: [Rect; Self::LENGTH],
     glyph_size: [Vec2; Self::LENGTH],
@@ -352,8 +621,42 @@ impl CharRenderer {
 enum ModalState {
     Settings,
     SetPosition(i64, i64),
+    ConfirmDiscard {
+        action: DiscardAction,
+        stats: ProgramStats,
+        confirmed: bool,
+    },
+    Stats(ProgramStats),
+}
+
+/// Summary of the current program, shown before it gets thrown away
+struct ProgramStats {
+    cells: usize,
+    bounds: Option<(Position, Position)>,
+    /// Stack length and output length, if running
+    playing: Option<(usize, usize)>,
+}
+
+/// Actions that replace the current program
+enum DiscardAction {
+    New,
+    Open,
+    Paste,
+    Preset(&'static include_dir::File<'static>),
+    UserPreset(String, String),
+}
+
+struct TraceReplay {
+    trace: Vec<TraceEntry>,
+    index: usize,
+    follow: bool,
 }
 
+type TraceChannel = (
+    Sender<Option<Vec<TraceEntry>>>,
+    Receiver<Option<Vec<TraceEntry>>>,
+);
+
 #[derive(Clone)]
 enum File {
     Handle(FileHandle),
@@ -375,27 +678,49 @@ pub struct App {
         Sender<(FileHandle, Option<String>)>,
         Receiver<(FileHandle, Option<String>)>,
     ),
+    trace_channel: TraceChannel,
+    trace_replay: Option<TraceReplay>,
     settings: Settings,
     mode: Mode,
     scene_rect: Rect,
+    graphics_scene_rect: Rect,
+    /// When the canvas was last copied into `texture`
+    canvas_uploaded: Instant,
     open_modal: Option<ModalState>,
     scene_offset: (i64, i64),
+    /// Whether the view was following something last frame
+    following: bool,
     cursor_pos: (i64, i64),
     popup_pos: Option<(i64, i64)>,
     char_renderer: CharRenderer,
     file: Option<File>,
+    window_title: String,
+    /// Size of a just loaded program that doesn't fit in Befunge-93's 80x25
+    oversized_notice: Option<(i64, i64)>,
+    /// Frame a clipboard read was requested on, for loading it as a new program
+    paste_requested: Option<u64>,
+    /// Name typed in for saving the program as a preset
+    preset_name: String,
+    /// Step typed in to jump to
+    goto_step: usize,
+    /// Step being fast forwarded to, a bit more each frame
+    step_target: Option<usize>,
+    /// Size typed in for resizing the canvas
+    canvas_size: (usize, usize),
+    /// Whether the canvas was changed from the UI and should be redrawn straight away
+    canvas_edited: bool,
+    /// Settings from before "Reset all settings", until the settings modal closes
+    settings_before_reset: Option<Box<Settings>>,
 }
 
 fn poss(pos: (f32, f32)) -> Pos2 {
     Pos2::new((pos.0) * 13.0, (pos.1) * 17.0)
 }
 
+/// The cell `pos` is in
 fn poss_reverse(pos: Pos2, offset: (i64, i64)) -> (i64, i64) {
-    let x = pos.x / 13.0;
-    let x = if x.is_sign_negative() { x - 1.0 } else { x };
-
-    let y = pos.y / 17.0;
-    let y = if y.is_sign_negative() { y - 1.0 } else { y };
+    let x = (pos.x / 13.0).floor();
+    let y = (pos.y / 17.0).floor();
     (
         (x as i64).saturating_add(offset.0),
         (y as i64).saturating_add(offset.1),
@@ -462,20 +787,15 @@ impl Mode {
     fn swap_mode(&mut self, settings: &Settings) {
         *self = match self.clone() {
             Mode::Editing {
-                fungespace, stdin, ..
+                fungespace,
+                stdin,
+                breakpoints,
+                ..
             } => {
-                let mut bf_state = match settings.befunge_version {
-                    BefungeVersionDiscriminants::Befunge93 => Box::new(BefungeVersion::Befunge93(
-                        befunge93::State::new_from_fungespace(fungespace.clone()),
-                    )),
-                    BefungeVersionDiscriminants::Befunge93Mini => {
-                        Box::new(BefungeVersion::Befunge93Mini(
-                            befunge93mini::State::new_from_fungespace(fungespace.clone()),
-                        ))
-                    }
-                };
+                let mut bf_state = Box::new(Self::new_bf_state(settings, fungespace.clone()));
 
                 *bf_state.stdin() = stdin.clone();
+                *bf_state.breakpoints() = breakpoints;
 
                 Mode::Playing {
                     snapshot: (fungespace.clone(), stdin.clone()),
@@ -487,77 +807,214 @@ impl Mode {
                     follow: false,
                     speed: 5,
                     error_state: None,
+                    loop_detector: LoopDetector::default(),
+                    step_history: VecDeque::new(),
                 }
             }
             Mode::Playing {
-                snapshot, bf_state, ..
+                snapshot,
+                mut bf_state,
+                ..
             } => Mode::Editing {
                 undos: Vec::new(),
                 redos: Vec::new(),
                 cursor_state: CursorState::new(bf_state.cursor_position()),
                 fungespace: snapshot.0,
                 stdin: snapshot.1,
+                breakpoints: std::mem::take(bf_state.breakpoints()),
             },
         };
     }
 
+    fn new_bf_state(settings: &Settings, fungespace: FungeSpace) -> BefungeVersion {
+        let mut bf_state = match settings.befunge_version {
+            BefungeVersionDiscriminants::Befunge93 => {
+                BefungeVersion::Befunge93(befunge93::State::new_from_fungespace(fungespace))
+            }
+            BefungeVersionDiscriminants::Befunge93Mini => {
+                BefungeVersion::Befunge93Mini(befunge93mini::State::new_from_fungespace(fungespace))
+            }
+        };
+        if settings.locked_seed.0 {
+            bf_state.set_seed(settings.locked_seed.1);
+        }
+        // a malformed stack is pointed out where it's entered
+        let stack = parse_stack(&settings.initial_stack).unwrap_or_default();
+        bf_state.set_start(
+            &stack,
+            settings.initial_direction,
+            settings.initial_position,
+        );
+        bf_state
+    }
+
+    /// Restart the current run from the snapshot taken when entering Playing mode,
+    /// keeping the breakpoints and watchpoints
+    fn reset(&mut self, settings: &Settings) {
+        if let Mode::Playing {
+            snapshot,
+            bf_state,
+            running,
+            error_state,
+            loop_detector,
+            instruction_since,
+            step_history,
+            ..
+        } = self
+        {
+            *running = false;
+            *error_state = None;
+            *loop_detector = LoopDetector::default();
+            step_history.clear();
+            *instruction_since = 0;
+            // teeny bit wasteful
+            let breakpoints = bf_state.breakpoints().clone();
+            let watchpoints = bf_state.watchpoints().clone();
+            **bf_state = Self::new_bf_state(settings, snapshot.0.clone());
+            *bf_state.breakpoints() = breakpoints;
+            *bf_state.watchpoints() = watchpoints;
+            *bf_state.stdin() = snapshot.1.clone();
+        }
+    }
+
+    /// Steps requested by the user, remembering the prior state so it can be stepped back to.
+    /// Stops early on breakpoints and errors
+    /// Remembers the current state, so step back can return to it
+    fn save_snapshot(bf_state: &mut BefungeVersion, step_history: &mut VecDeque<StateSnapshot>) {
+        if step_history.len() >= MAX_STEP_HISTORY {
+            step_history.pop_front();
+            bf_state.forget_snapshots_before(step_history.front());
+        }
+        step_history.push_back(bf_state.snapshot());
+    }
+
+    fn manual_step(
+        bf_state: &mut BefungeVersion,
+        running: &mut bool,
+        error_state: &mut Option<(String, Position)>,
+        loop_detector: &mut LoopDetector,
+        step_history: &mut VecDeque<StateSnapshot>,
+        settings: &Settings,
+        count: usize,
+    ) -> StepResult {
+        *running = false;
+        Mode::save_snapshot(bf_state, step_history);
+        let mut result =
+            Mode::step_befunge_inner(bf_state, running, error_state, loop_detector, settings);
+        for _ in 1..count {
+            if result.stop {
+                break;
+            }
+            result =
+                Mode::step_befunge_inner(bf_state, running, error_state, loop_detector, settings);
+        }
+        Mode::trim_step_history(bf_state, step_history);
+        result
+    }
+
+    /// Forgets the oldest snapshots while the field writes journaled for them are
+    /// past [`MAX_JOURNAL_LENGTH`], so a long run after a manual step can't keep
+    /// every write. Journaling stops once there are no snapshots left
+    fn trim_step_history(
+        bf_state: &mut BefungeVersion,
+        step_history: &mut VecDeque<StateSnapshot>,
+    ) {
+        while bf_state.journal_len() > MAX_JOURNAL_LENGTH && step_history.pop_front().is_some() {
+            bf_state.forget_snapshots_before(step_history.front());
+        }
+        if step_history.is_empty() {
+            bf_state.forget_snapshots_before(None);
+        }
+    }
+
+    /// Steps towards step `target` for up to `budget`, returning whether it's done,
+    /// either by getting there or by hitting a breakpoint or error
+    fn run_to_step(&mut self, target: usize, settings: &Settings, budget: Duration) -> bool {
+        let Mode::Playing {
+            bf_state,
+            running,
+            error_state,
+            loop_detector,
+            step_history,
+            ..
+        } = self
+        else {
+            return true;
+        };
+        Self::trim_step_history(bf_state, step_history);
+        let start = Instant::now();
+        while bf_state.instruction_count() < target {
+            for _ in 0..(target - bf_state.instruction_count()).min(10_000) {
+                let result = Self::step_befunge_inner(
+                    bf_state,
+                    running,
+                    error_state,
+                    loop_detector,
+                    settings,
+                );
+                if result.stop && !matches!(result.status, StepStatus::SyncFrame) {
+                    return true;
+                }
+            }
+            if start.elapsed() > budget {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn step_back(
+        bf_state: &mut BefungeVersion,
+        running: &mut bool,
+        error_state: &mut Option<(String, Position)>,
+        step_history: &mut VecDeque<StateSnapshot>,
+    ) {
+        if let Some(snapshot) = step_history.pop_back() {
+            *running = false;
+            *error_state = None;
+            bf_state.restore(&snapshot);
+            Self::trim_step_history(bf_state, step_history);
+        }
+    }
+
     fn step_befunge_inner(
         bf_state: &mut BefungeVersion,
         running: &mut bool,
-        error_state: &mut Option<&'static str>,
+        error_state: &mut Option<(String, Position)>,
+        loop_detector: &mut LoopDetector,
         settings: &Settings,
-    ) -> bool {
-        let step_state = bf_state.step(settings);
-        match step_state {
+    ) -> StepResult {
+        let output_len = bf_state.stdout().len();
+        let status = bf_state.step(settings);
+        if *running && settings.loop_detection.0 {
+            loop_detector.observe(bf_state, settings.loop_detection.1);
+        }
+        let wandered = *running
+            && settings.wander_detection.0
+            && loop_detector.observe_bounds(bf_state, settings.wander_detection.1);
+        let mut stop = match &status {
             StepStatus::Normal | StepStatus::NormalNoStep => false,
             StepStatus::Breakpoint => {
                 *running = false;
                 true
             }
             StepStatus::Error(error) => {
-                use InvalidOperationBehaviour as IOpBehav;
-
-                match bf_state {
-                    BefungeVersion::Befunge93(bf_state) => {
-                        match settings.invalid_operation_behaviour {
-                            IOpBehav::Reflect => {
-                                bf_state.direction = bf_state.direction.reverse();
-                                bf_state.step_position(settings);
-                                false
-                            }
-                            IOpBehav::Halt => {
-                                *error_state = Some(error);
-                                *running = false;
-                                true
-                            }
-                            IOpBehav::Ignore => {
-                                bf_state.step_position(settings);
-                                false
-                            }
-                        }
-                    }
-                    BefungeVersion::Befunge93Mini(bf_state) => {
-                        match settings.invalid_operation_behaviour {
-                            IOpBehav::Reflect => {
-                                bf_state.direction = bf_state.direction.reverse();
-                                bf_state.step_position(settings);
-                                false
-                            }
-                            IOpBehav::Halt => {
-                                *error_state = Some(error);
-                                *running = false;
-                                true
-                            }
-                            IOpBehav::Ignore => {
-                                bf_state.step_position(settings);
-                                false
-                            }
-                        }
-                    }
-                }
+                *error_state = Some((error.clone(), bf_state.cursor_position()));
+                *running = false;
+                true
             }
             StepStatus::SyncFrame => true,
+        };
+        let output = bf_state.stdout().len() != output_len;
+        if *running && settings.pause_on_output && output {
+            *running = false;
+            stop = true;
         }
+        if wandered && settings.pause_on_wander {
+            *running = false;
+            stop = true;
+        }
+        StepResult { status, stop }
     }
 
     fn step_befunge(&mut self, settings: &Settings) {
@@ -570,11 +1027,23 @@ impl Mode {
                 bf_state,
                 running,
                 error_state,
+                loop_detector,
+                step_history,
                 ..
             } => {
+                // writes made while running are journaled too, for the last manual step
+                Self::trim_step_history(bf_state, step_history);
                 if settings.run_until_breakpoint && *speed == 20 {
                     loop {
-                        if Self::step_befunge_inner(bf_state, running, error_state, settings) {
+                        if Self::step_befunge_inner(
+                            bf_state,
+                            running,
+                            error_state,
+                            loop_detector,
+                            settings,
+                        )
+                        .stop
+                        {
                             return;
                         }
                     }
@@ -595,7 +1064,13 @@ impl Mode {
                 if time_per_step {
                     match speed {
                         ..6 => {
-                            Self::step_befunge_inner(bf_state, running, error_state, settings);
+                            Self::step_befunge_inner(
+                                bf_state,
+                                running,
+                                error_state,
+                                loop_detector,
+                                settings,
+                            );
                         }
                         6..=9 => {
                             for _ in 0..=*speed - 6 {
@@ -603,8 +1078,11 @@ impl Mode {
                                     bf_state,
                                     running,
                                     error_state,
+                                    loop_detector,
                                     settings,
-                                ) {
+                                )
+                                .stop
+                                {
                                     break;
                                 };
                             }
@@ -615,8 +1093,11 @@ impl Mode {
                                     bf_state,
                                     running,
                                     error_state,
+                                    loop_detector,
                                     settings,
-                                ) {
+                                )
+                                .stop
+                                {
                                     break;
                                 };
                             }
@@ -627,8 +1108,11 @@ impl Mode {
                                     bf_state,
                                     running,
                                     error_state,
+                                    loop_detector,
                                     settings,
-                                ) {
+                                )
+                                .stop
+                                {
                                     break 'loopy;
                                 }
                             }
@@ -696,9 +1180,14 @@ impl App {
 
         Self {
             scene_rect: Rect::ZERO,
+            graphics_scene_rect: Rect::ZERO,
+            canvas_uploaded: Instant::now(),
             text_channel: channel(),
+            trace_channel: channel(),
+            trace_replay: None,
             settings,
             scene_offset: (0, 0),
+            following: false,
             cursor_pos: (0, 0),
             popup_pos: None,
             open_modal: None,
@@ -708,6 +1197,7 @@ impl App {
                 cursor_state: CursorState::default(),
                 fungespace: FungeSpace::default(),
                 stdin: String::new(),
+                breakpoints: HashSet::default(),
             },
             texture: cc.egui_ctx.load_texture(
                 "noise",
@@ -716,10 +1206,59 @@ impl App {
             ),
             char_renderer: CharRenderer::empty(),
             file: None,
+            window_title: String::new(),
+            oversized_notice: None,
+            paste_requested: None,
+            preset_name: String::new(),
+            goto_step: 0,
+            step_target: None,
+            canvas_size: (0, 0),
+            canvas_edited: false,
+            settings_before_reset: None,
         }
     }
 }
 
+/// What the speed slider's `speed` means, matching how `step_befunge` runs it
+fn speed_label(speed: u8) -> String {
+    match speed {
+        ..=5 => format!("{} steps/s", 1 << speed.saturating_sub(1)),
+        6..=9 => format!("{} steps/s", (usize::from(speed) - 5) * 32),
+        10..=15 => format!("{} steps/s", (2_usize.pow(u32::from(speed) - 8) + 1) * 32),
+        16..=19 => format!("Max for {}ms/frame", 4 << (speed - 16)),
+        _ => "Max".to_owned(),
+    }
+}
+
+/// Whether keyboard shortcuts and editor keys should be handled. Anything with
+/// keyboard focus, like the input or find boxes, gets the keys instead, so every
+/// hotkey must be checked against this
+fn hotkeys_enabled(ctx: &egui::Context) -> bool {
+    ctx.memory(|mem| mem.focused().is_none())
+}
+
+/// Why `&` can't read a number from the start of `input` yet, if it can't
+fn number_input_problem(input: &str) -> Option<&'static str> {
+    let digits = input.chars().take_while(char::is_ascii_digit).count();
+    if input.is_empty() {
+        None
+    } else if digits == 0 {
+        Some("& needs a number, but the input doesn't start with one")
+    } else if digits == input.len() {
+        Some("End the number with a space")
+    } else if !input[digits..].starts_with(' ') {
+        Some("& needs a space after the number")
+    } else {
+        None
+    }
+}
+
+/// How many cells `pos` is from `offset`, clamped to well beyond anything on
+/// screen so it converts to f32 without losing precision
+fn relative_cell(pos: i64, offset: i64) -> f32 {
+    pos.saturating_sub(offset).clamp(-100_000, 100_000) as f32
+}
+
 fn recter(pos: (i64, i64), offset: (i64, i64)) -> Rect {
     Rect::from_min_size(
         poss(((pos.0 - offset.0) as f32, (pos.1 - offset.1) as f32)),
@@ -749,6 +1288,16 @@ impl eframe::App for App {
 
         self.char_renderer.update(ctx);
 
+        if let Some(target) = self.step_target {
+            if self
+                .mode
+                .run_to_step(target, &self.settings, Duration::from_millis(16))
+            {
+                self.step_target = None;
+            }
+            ctx.request_repaint();
+        }
+
         if let Mode::Playing { running, speed, .. } = self.mode
             && running
         {
@@ -760,26 +1309,82 @@ impl eframe::App for App {
             }
         }
 
+        // the clipboard contents arrive as a paste event on a later frame
+        if let Some(requested) = self.paste_requested
+            && ctx.cumulative_frame_nr() > requested
+        {
+            self.paste_requested = None;
+            let text = ctx.input_mut(|input| {
+                let index = input
+                    .events
+                    .iter()
+                    .position(|event| matches!(event, egui::Event::Paste(_)))?;
+                match input.events.remove(index) {
+                    egui::Event::Paste(text) => Some(text),
+                    _ => None,
+                }
+            });
+            // an empty or non-text clipboard leaves the current program alone
+            if let Some(text) = text
+                && !text.trim().is_empty()
+            {
+                self.file = None;
+                self.mode = Mode::Editing {
+                    undos: Vec::new(),
+                    redos: Vec::new(),
+                    cursor_state: CursorState::default(),
+                    fungespace: FungeSpace::new_from_string(
+                        &text,
+                        self.settings.loaded_tab_width(),
+                    ),
+                    stdin: String::new(),
+                    breakpoints: HashSet::default(),
+                };
+                self.check_program_size();
+            }
+        }
+
         if let Ok((file, text)) = self.text_channel.1.try_recv() {
             self.file = Some(File::Handle(file));
-            if let Mode::Editing {
-                fungespace: FungeSpace { dirty, .. },
-                ..
-            } = &mut self.mode
-            {
-                *dirty = false;
+            match &mut self.mode {
+                Mode::Editing { fungespace, .. } => fungespace.dirty = false,
+                // the modified field isn't the program, so it being saved doesn't count
+                Mode::Playing { snapshot, .. } if !self.settings.save_modified_field => {
+                    snapshot.0.dirty = false
+                }
+                Mode::Playing { .. } => (),
             }
             if let Some(text) = text {
                 self.mode = Mode::Editing {
                     undos: Vec::new(),
                     redos: Vec::new(),
                     cursor_state: CursorState::default(),
-                    fungespace: FungeSpace::new_from_string(&text),
+                    fungespace: FungeSpace::new_from_string(
+                        &text,
+                        self.settings.loaded_tab_width(),
+                    ),
                     stdin: String::new(),
-                }
+                    breakpoints: HashSet::default(),
+                };
+                self.check_program_size();
+            }
+        }
+
+        if let Ok(trace) = self.trace_channel.1.try_recv() {
+            if let Some(trace) = trace {
+                self.trace_replay = Some(TraceReplay {
+                    trace,
+                    index: 0,
+                    follow: false,
+                });
+            } else {
+                log::warn!("Failed to parse trace file");
             }
         }
 
+        self.trace_replay_window(ctx);
+        self.update_window_title(ctx);
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             self.menu_bar(ui, ctx);
         });
@@ -856,6 +1461,27 @@ impl eframe::App for App {
                         *instruction_since = bf_state.instruction_count();
                         *time_since_avg = now;
                     };
+
+                    ui.separator();
+                    let status = match &self.mode {
+                        Mode::Playing { bf_state, .. } => format!(
+                            "Playing | {:?}{} | Stack: {}",
+                            bf_state.cursor_direction(),
+                            if bf_state.string_mode() { " | String mode" } else { "" },
+                            bf_state.stack_len()
+                        ),
+                        Mode::Editing {
+                            cursor_state,
+                            fungespace,
+                            ..
+                        } => format!(
+                            "Editing | {:?}{} | Cells: {}",
+                            cursor_state.direction,
+                            if cursor_state.string_mode { " | String mode" } else { "" },
+                            fungespace.non_space_count(),
+                        ),
+                    };
+                    ui.label(status);
                 });
             });
         });
@@ -871,6 +1497,8 @@ impl eframe::App for App {
             puffin::profile_scope!("central panel");
 
             puffin::profile_scope!("control bar");
+            let mut reset = false;
+            let mut go_to_step = None;
             match &mut self.mode {
                 Mode::Playing {
                     bf_state,
@@ -878,10 +1506,21 @@ impl eframe::App for App {
                     follow,
                     speed,
                     error_state,
-                    snapshot,
+                    loop_detector,
+                    step_history,
                     ..
                 } => {
                     ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(
+                                !step_history.is_empty(),
+                                egui::Button::new(icon!(icons::ICON_UNDO, "Step back"))
+                                    .shortcut_text(icons::ICON_ARROW_LEFT_ALT),
+                            )
+                            .clicked()
+                        {
+                            Mode::step_back(bf_state, running, error_state, step_history);
+                        }
                         ui.scope(|ui| {
                             if error_state.is_some() {
                                 ui.disable();
@@ -893,13 +1532,84 @@ impl eframe::App for App {
                                 )
                                 .clicked()
                             {
+                                Mode::manual_step(
+                                    bf_state,
+                                    running,
+                                    error_state,
+                                    loop_detector,
+                                    step_history,
+                                    &self.settings,
+                                    1,
+                                );
+                            }
+                            if ui
+                                .add(egui::Button::new(icon!(icons::ICON_FAST_FORWARD, "Run")))
+                                .on_hover_text(
+                                    "Run this many steps, stopping early on a breakpoint or error",
+                                )
+                                .clicked()
+                            {
+                                // spread over frames like going to a step, and undone
+                                // by a single step back
                                 *running = false;
-                                Mode::step_befunge_inner(
+                                Mode::save_snapshot(bf_state, step_history);
+                                self.step_target = Some(
+                                    bf_state
+                                        .instruction_count()
+                                        .saturating_add(self.settings.run_steps),
+                                );
+                            }
+                            ui.add(
+                                egui::DragValue::new(&mut self.settings.run_steps)
+                                    .range(1..=100_000_000)
+                                    .suffix(" steps"),
+                            );
+                            if ui
+                                .add(egui::Button::new(icon!(icons::ICON_SKIP_NEXT, "Frame")))
+                                .on_hover_text(
+                                    "Run until the next u draws a frame, stopping early on a breakpoint",
+                                )
+                                .clicked()
+                            {
+                                Mode::manual_step(
                                     bf_state,
                                     running,
                                     error_state,
+                                    loop_detector,
+                                    step_history,
                                     &self.settings,
+                                    MAX_FRAME_STEPS,
+                                );
+                            }
+                            if let Some(target) = self.step_target {
+                                let count = bf_state.instruction_count();
+                                ui.add(
+                                    egui::ProgressBar::new(count as f32 / target as f32)
+                                        .desired_width(120.0)
+                                        .text(format!("Step {count}/{target}")),
                                 );
+                                if ui.button("Cancel").clicked() {
+                                    self.step_target = None;
+                                }
+                            } else {
+                                ui.menu_button("Go to step", |ui| {
+                                    ui.add(
+                                        egui::DragValue::new(&mut self.goto_step)
+                                            .range(0..=usize::MAX),
+                                    );
+                                    if ui
+                                        .button("Go")
+                                        .on_hover_text(
+                                            "Runs from the start if the step has already passed, \
+                                            with the same seed so ? picks the same way. \
+                                            Stops early on a breakpoint",
+                                        )
+                                        .clicked()
+                                    {
+                                        go_to_step = Some(self.goto_step);
+                                        ui.close();
+                                    }
+                                });
                             }
                             if ui
                                 .add(
@@ -917,60 +1627,179 @@ impl eframe::App for App {
                         });
                         if ui
                             .add(
-                                egui::Button::new(icon!(icons::ICON_REPLAY, "Reset"))
+                                egui::Button::new(icon!(icons::ICON_STOP, "Stop"))
                                     .shortcut_text("R"),
                             )
+                            .on_hover_text(
+                                "Stop running and go back to the start of the program, \
+                                keeping breakpoints and watchpoints",
+                            )
                             .clicked()
                         {
-                            *running = false;
-                            *error_state = None;
-                            // teeny bit wasteful
-                            let breakpoints = bf_state.breakpoints().clone();
-                            **bf_state = match self.settings.befunge_version {
-                                BefungeVersionDiscriminants::Befunge93 => {
-                                    BefungeVersion::Befunge93(
-                                        befunge93::State::new_from_fungespace(snapshot.0.clone()),
-                                    )
-                                }
-                                BefungeVersionDiscriminants::Befunge93Mini => {
-                                    BefungeVersion::Befunge93Mini(
-                                        befunge93mini::State::new_from_fungespace(
-                                            snapshot.0.clone(),
-                                        ),
-                                    )
-                                }
-                            };
-                            *bf_state.breakpoints() = breakpoints;
-                            *bf_state.stdin() = snapshot.1.clone();
+                            reset = true;
                         };
 
-                        checkbox_with_underline(ui, follow, "Follow");
+                        let start_changed = ui
+                            .menu_button(icon!(icons::ICON_TUNE, "Start"), |ui| {
+                                let mut changed = false;
+                                ui.label("Initial stack (bottom first):");
+                                let stack = &mut self.settings.initial_stack;
+                                changed |= ui
+                                    .add(egui::TextEdit::singleline(stack).hint_text("1 2 3"))
+                                    .changed();
+                                if let Err(error) = parse_stack(stack) {
+                                    ui.label(
+                                        RichText::new(error).color(ui.visuals().error_fg_color),
+                                    );
+                                }
+                                ui.label("Initial direction:");
+                                ui.horizontal(|ui| {
+                                    let direction = &mut self.settings.initial_direction;
+                                    for (dir, name) in [
+                                        (Direction::East, "East"),
+                                        (Direction::South, "South"),
+                                        (Direction::West, "West"),
+                                        (Direction::North, "North"),
+                                    ] {
+                                        changed |= ui.radio_value(direction, dir, name).changed();
+                                    }
+                                });
+                                ui.label("Initial position:");
+                                ui.horizontal(|ui| {
+                                    let (x, y) = &mut self.settings.initial_position;
+                                    ui.label("x");
+                                    changed |= ui
+                                        .add(egui::DragValue::new(x).range(0..=i64::MAX))
+                                        .changed();
+                                    ui.label("y");
+                                    changed |= ui
+                                        .add(egui::DragValue::new(y).range(0..=i64::MAX))
+                                        .changed();
+                                });
+                                ui.label(RichText::new("Used from the next reset").weak());
+                                changed
+                            })
+                            .inner
+                            .unwrap_or(false);
+                        // nothing has run yet, so it's safe to apply straight away
+                        if start_changed && bf_state.instruction_count() == 0 {
+                            reset = true;
+                        }
 
-                        ui.add(egui::Slider::new(speed, 1..=20).text("speed"));
-                    });
+                        checkbox_with_underline(ui, follow, "Follow");
+                        if ui
+                            .button(icon!(icons::ICON_LAYERS_CLEAR, "Clear trails"))
+                            .on_hover_text("Clear the position, put and get history straight away")
+                            .clicked()
+                        {
+                            bf_state.pos_history().clear();
+                            bf_state.put_history().clear();
+                            bf_state.get_history().clear();
+                        }
+                        ui.checkbox(&mut self.settings.pause_on_output, "Pause on output")
+                            .on_hover_text("Pause whenever the program prints something");
 
-                    if self.settings.display_debug_info {
                         ui.separator();
-                        ui.horizontal(|ui| {
-                            ui.vertical(|ui| {
-                                ui.label("execution state");
-                                ui.label(format!("{:?}", error_state));
-                            });
-                            ui.vertical(|ui| {
-                                ui.label("step");
-                                ui.label(bf_state.instruction_count().to_string());
-                            });
-                            ui.vertical(|ui| {
-                                ui.label("location");
-                                ui.label(format!("{:?}", bf_state.cursor_position()));
-                            });
-                            ui.vertical(|ui| {
-                                ui.label("direction");
-                                ui.label(format!("{:?}", bf_state.cursor_direction()));
-                            });
-                            ui.vertical(|ui| {
-                                ui.label("string mode");
-                                ui.label(format!("{:?}", bf_state.string_mode()));
+                        let locked_seed = &mut self.settings.locked_seed;
+                        if ui
+                            .checkbox(&mut locked_seed.0, "Lock seed")
+                            .on_hover_text("Reuse this seed for '?' in every run")
+                            .changed()
+                        {
+                            locked_seed.1 = bf_state.seed();
+                        }
+                        let mut seed = bf_state.seed();
+                        if ui
+                            .add(
+                                egui::DragValue::new(&mut seed)
+                                    .range(0..=MAX_SEED)
+                                    .prefix("Seed: "),
+                            )
+                            .on_hover_text(
+                                "Takes effect immediately, reset to replay the whole run",
+                            )
+                            .changed()
+                        {
+                            bf_state.set_seed(seed);
+                            if locked_seed.0 {
+                                locked_seed.1 = seed;
+                            }
+                        }
+                        if ui
+                            .button(icons::ICON_CASINO)
+                            .on_hover_text("Reroll seed")
+                            .clicked()
+                        {
+                            let seed = random_seed();
+                            bf_state.set_seed(seed);
+                            if locked_seed.0 {
+                                locked_seed.1 = seed;
+                            }
+                        }
+
+                        ui.add(
+                            egui::Slider::new(speed, 1..=20)
+                                .text("speed")
+                                .custom_formatter(|speed, _| speed_label(speed as u8)),
+                        )
+                        .on_hover_text(
+                            "From 16 up, steps run as fast as possible for part of every frame, \
+                            so the CPU is kept busy",
+                        );
+
+                        if self.settings.record_trace {
+                            let trace = bf_state.trace();
+                            if ui
+                                .add_enabled(
+                                    !trace.is_empty(),
+                                    egui::Button::new(icon!(icons::ICON_SAVE, "Save trace")),
+                                )
+                                .on_hover_text(format!(
+                                    "{} steps recorded (max {})",
+                                    trace.len(),
+                                    TraceEntry::MAX_TRACE_LENGTH
+                                ))
+                                .clicked()
+                            {
+                                let contents = TraceEntry::to_csv(trace);
+                                let task = rfd::AsyncFileDialog::new()
+                                    .set_file_name("trace.csv")
+                                    .save_file();
+
+                                let ctx = ui.ctx().clone();
+                                execute(async move {
+                                    let file = task.await;
+                                    if let Some(file) = file {
+                                        _ = file.write(contents.as_bytes()).await;
+                                        ctx.request_repaint();
+                                    }
+                                });
+                            }
+                        }
+                    });
+
+                    if self.settings.display_debug_info {
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.vertical(|ui| {
+                                ui.label("execution state");
+                                ui.label(format!("{:?}", error_state));
+                            });
+                            ui.vertical(|ui| {
+                                ui.label("step");
+                                ui.label(bf_state.instruction_count().to_string());
+                            });
+                            ui.vertical(|ui| {
+                                ui.label("location");
+                                ui.label(format!("{:?}", bf_state.cursor_position()));
+                            });
+                            ui.vertical(|ui| {
+                                ui.label("direction");
+                                ui.label(format!("{:?}", bf_state.cursor_direction()));
+                            });
+                            ui.vertical(|ui| {
+                                ui.label("string mode");
+                                ui.label(format!("{:?}", bf_state.string_mode()));
                             });
                         });
                     }
@@ -1023,6 +1852,24 @@ impl eframe::App for App {
                 }
             }
 
+            if reset {
+                self.mode.reset(&self.settings);
+                self.step_target = None;
+            }
+            if let Some(target) = go_to_step
+                && let Mode::Playing { bf_state, .. } = &mut self.mode
+            {
+                if target < bf_state.instruction_count() {
+                    // rerun from the start the same way it went the first time
+                    let seed = bf_state.seed();
+                    self.mode.reset(&self.settings);
+                    if let Mode::Playing { bf_state, .. } = &mut self.mode {
+                        bf_state.set_seed(seed);
+                    }
+                }
+                self.step_target = Some(target);
+            }
+
             ui.add_space(3.0);
 
             egui::Frame::group(ui.style())
@@ -1031,7 +1878,7 @@ impl eframe::App for App {
                     ui.set_min_height(100.0);
 
                     self.befunge_scene(ui);
-                    if self.open_modal.is_none() && ctx.memory(|mem| mem.focused().is_none()) {
+                    if self.open_modal.is_none() && hotkeys_enabled(ctx) {
                         self.befunge_input(ui);
                     }
                 });
@@ -1043,37 +1890,39 @@ impl App {
     fn befunge_input(&mut self, ui: &mut egui::Ui) {
         puffin::profile_function!();
 
+        let copy_program = ui.input_mut(|e| e.consume_shortcut(&SHORTCUT_COPY_PROGRAM));
+        if copy_program {
+            ui.ctx().copy_text(self.serialize_program());
+        }
+
         ui.input_mut(|e| {
             if e.consume_shortcut(&SHORTCUT_SWAP_MODE) {
                 self.mode.swap_mode(&self.settings);
             }
 
+            if e.consume_key(Modifiers::SHIFT, egui::Key::F9) {
+                self.jump_to_breakpoint(false);
+            } else if e.consume_key(Modifiers::NONE, egui::Key::F9) {
+                self.jump_to_breakpoint(true);
+            }
+
+            let mut reset = false;
             match &mut self.mode {
                 Mode::Playing {
                     bf_state,
                     running,
-                    snapshot,
                     error_state,
+                    loop_detector,
                     follow,
+                    step_history,
                     ..
                 } => {
                     if e.consume_key(Modifiers::NONE, egui::Key::R) {
-                        *running = false;
-                        *error_state = None;
-                        // teeny bit wasteful
-                        let breakpoints = bf_state.breakpoints().clone();
-                        **bf_state = match self.settings.befunge_version {
-                            BefungeVersionDiscriminants::Befunge93 => BefungeVersion::Befunge93(
-                                befunge93::State::new_from_fungespace(snapshot.0.clone()),
-                            ),
-                            BefungeVersionDiscriminants::Befunge93Mini => {
-                                BefungeVersion::Befunge93Mini(
-                                    befunge93mini::State::new_from_fungespace(snapshot.0.clone()),
-                                )
-                            }
-                        };
-                        *bf_state.breakpoints() = breakpoints;
-                        *bf_state.stdin() = snapshot.1.clone();
+                        reset = true;
+                    }
+
+                    if e.consume_key(Modifiers::NONE, egui::Key::ArrowLeft) {
+                        Mode::step_back(bf_state, running, error_state, step_history);
                     }
 
                     if e.consume_key(Modifiers::NONE, egui::Key::F) {
@@ -1086,12 +1935,14 @@ impl App {
                         }
 
                         if e.consume_key(Modifiers::NONE, egui::Key::ArrowRight) {
-                            *running = false;
-                            Mode::step_befunge_inner(
+                            Mode::manual_step(
                                 bf_state,
                                 running,
                                 error_state,
+                                loop_detector,
+                                step_history,
                                 &self.settings,
+                                1,
                             );
                         }
                     }
@@ -1123,6 +1974,30 @@ impl App {
                         cursor_state.step_cursor_back(&self.settings);
                     }
 
+                    if e.consume_key(Modifiers::NONE, egui::Key::Tab) {
+                        let mut ops = vec![];
+                        for _ in 0..self.settings.tab_width {
+                            if self.settings.tab_inserts_spaces {
+                                ops.push((
+                                    cursor_state.location,
+                                    fungespace.get(cursor_state.location),
+                                ));
+                                fungespace.set(cursor_state.location, b' ' as i64);
+                            }
+                            cursor_state.step(&self.settings);
+                        }
+                        if !ops.is_empty() {
+                            undos.push((ops.into(), false));
+                            redos.clear();
+                        }
+                    }
+
+                    if e.consume_key(Modifiers::SHIFT, egui::Key::Tab) {
+                        for _ in 0..self.settings.tab_width {
+                            cursor_state.step_cursor_back(&self.settings);
+                        }
+                    }
+
                     let save_as = e.consume_shortcut(&SHORTCUT_SAVE_AS);
                     let save = e.consume_shortcut(&SHORTCUT_SAVE);
                     let reload = e.consume_shortcut(&SHORTCUT_RELOAD_FILE);
@@ -1140,7 +2015,7 @@ impl App {
                         }
 
                         let task = task.save_file();
-                        let contents = fungespace.serialize();
+                        let contents = self.settings.line_ending.apply(fungespace.serialize());
 
                         let ctx = ui.ctx().clone();
                         execute(async move {
@@ -1159,7 +2034,7 @@ impl App {
                         && let Some(File::Handle(file)) = self.file.clone()
                     {
                         let sender = self.text_channel.0.clone();
-                        let contents = fungespace.serialize();
+                        let contents = self.settings.line_ending.apply(fungespace.serialize());
 
                         let ctx = ui.ctx().clone();
                         execute(async move {
@@ -1231,17 +2106,32 @@ impl App {
                                 redos.clear();
                             }
                             egui::Event::Paste(text) => {
-                                let (mut x, mut y) = cursor_state.location;
+                                // (step per char, step per line)
+                                let (char_step, line_step) =
+                                    if self.settings.paste_follows_direction {
+                                        match cursor_state.direction {
+                                            Direction::East => ((1, 0), (0, 1)),
+                                            Direction::West => ((-1, 0), (0, 1)),
+                                            Direction::South => ((0, 1), (1, 0)),
+                                            Direction::North => ((0, -1), (1, 0)),
+                                        }
+                                    } else {
+                                        ((1, 0), (0, 1))
+                                    };
+                                let mut line_start = cursor_state.location;
+                                let (mut x, mut y) = line_start;
                                 let mut ops = vec![];
                                 for char in text.chars() {
                                     if char == '\n' {
-                                        y += 1;
-                                        x = cursor_state.location.0;
+                                        line_start.0 += line_step.0;
+                                        line_start.1 += line_step.1;
+                                        (x, y) = line_start;
                                         continue;
                                     };
                                     ops.push(((x, y), fungespace.get((x, y))));
                                     fungespace.set((x, y), char as i64);
-                                    x += 1
+                                    x += char_step.0;
+                                    y += char_step.1;
                                 }
                                 undos.push((ops.into(), false));
                                 redos.clear();
@@ -1251,6 +2141,10 @@ impl App {
                     }
                 }
             }
+
+            if reset {
+                self.mode.reset(&self.settings);
+            }
         });
     }
 
@@ -1260,83 +2154,102 @@ impl App {
             .max_inner_size([f32::INFINITY, f32::INFINITY])
             .zoom_range(0.01..=5.0);
 
-        if let Mode::Playing {
-            follow: true,
-            bf_state,
-            ..
-        } = &self.mode
-        {
-            self.scene_offset = bf_state.cursor_position();
-            self.scene_rect.set_center(poss((0.5, 0.5)));
+        let follow_pos = match (&self.trace_replay, &self.mode) {
+            (
+                Some(TraceReplay {
+                    trace,
+                    index,
+                    follow: true,
+                }),
+                _,
+            ) => trace.get(*index).map(|entry| entry.position),
+            (
+                _,
+                Mode::Playing {
+                    follow: true,
+                    bf_state,
+                    ..
+                },
+            ) => Some(bf_state.cursor_position()),
+            _ => None,
+        };
+
+        if let Some(follow_pos) = follow_pos {
+            // re-anchor on the followed cell without moving the view
+            let shift = poss((
+                (follow_pos.0 - self.scene_offset.0) as f32,
+                (follow_pos.1 - self.scene_offset.1) as f32,
+            ));
+            self.scene_offset = follow_pos;
+            self.scene_rect = self.scene_rect.translate(-shift.to_vec2());
+
+            let gap = poss((0.5, 0.5)) - self.scene_rect.center();
+            if !self.following || gap.length() > self.scene_rect.size().max_elem() {
+                // catch up straight away when follow is turned on, or the cursor jumps far
+                self.scene_rect.set_center(poss((0.5, 0.5)));
+            } else if gap.length() > 0.1 {
+                let dt = ui.input(|input| input.stable_dt).min(0.1);
+                let t = 1.0 - (-FOLLOW_SMOOTHING * dt).exp();
+                self.scene_rect = self.scene_rect.translate(gap * t);
+                ui.ctx().request_repaint();
+            }
             // disable panning
             ui.input_mut(|input| {
                 input.smooth_scroll_delta = Vec2::ZERO;
             });
             scene = scene.sense(Sense::HOVER);
         } else {
-            if self.scene_rect.left() >= 130.0 {
-                *self.scene_rect.left_mut() -= 130.0;
-                *self.scene_rect.right_mut() -= 130.0;
-                self.scene_offset.0 += 10;
-            };
-
-            if self.scene_rect.left() <= -130.0 {
-                *self.scene_rect.left_mut() += 130.0;
-                *self.scene_rect.right_mut() += 130.0;
-                self.scene_offset.0 -= 10;
-            };
-
-            if self.scene_rect.top() >= 170.0 {
-                *self.scene_rect.top_mut() -= 170.0;
-                *self.scene_rect.bottom_mut() -= 170.0;
-                self.scene_offset.1 += 10;
-            };
-
-            if self.scene_rect.top() <= -170.0 {
-                *self.scene_rect.top_mut() += 170.0;
-                *self.scene_rect.bottom_mut() += 170.0;
-                self.scene_offset.1 -= 10;
-            };
+            // rebase by whole 10 cell chunks, however far the view moved, so the
+            // rect stays near the origin where f32 is precise
+            let chunk = poss((10.0, 10.0));
+            let chunks_x = (self.scene_rect.left() / chunk.x).trunc();
+            let chunks_y = (self.scene_rect.top() / chunk.y).trunc();
+            self.scene_rect = self
+                .scene_rect
+                .translate(-Vec2::new(chunks_x * chunk.x, chunks_y * chunk.y));
+            self.scene_offset.0 += chunks_x as i64 * 10;
+            self.scene_offset.1 += chunks_y as i64 * 10;
         }
+        self.following = follow_pos.is_some();
 
         let response = scene
             .show(ui, &mut self.scene_rect, |ui| {
                 let painter = ui.painter();
                 let clip_rect = painter.clip_rect();
 
+                // everything is drawn relative to the offset, as cells, so the numbers
+                // stay small enough for f32 wherever the view is
+                let offset = self.scene_offset;
+                let border_pos = self.settings.befunge_version.border_positions();
+                let (min_x, min_y) = (
+                    relative_cell(border_pos.0.0, offset.0),
+                    relative_cell(border_pos.0.1, offset.1),
+                );
+                let (max_x, max_y) = (
+                    relative_cell(border_pos.1.0, offset.0),
+                    relative_cell(border_pos.1.1, offset.1),
+                );
+
                 // Grid dots
                 {
                     puffin::profile_scope!("grid dots");
 
-                    let border_pos = self.settings.befunge_version.border_positions();
-
                     // TODO: remove overlap of bottom/right dots with border line
                     if clip_rect.height() < 2500.0 {
-                        let mut y = f32::max(
-                            (clip_rect.top() / 17.0).round() * 17.0,
-                            17.0 - (self.scene_offset.1 as f32 * 17.0),
-                        );
+                        let mut y = f32::max((clip_rect.top() / 17.0).round() * 17.0, (min_y + 1.0) * 17.0);
 
                         loop {
-                            let mut x = f32::max(
-                                (clip_rect.left() / 13.0).round() * 13.0,
-                                13.0 - (self.scene_offset.0 as f32 * 13.0),
-                            );
+                            let mut x =
+                                f32::max((clip_rect.left() / 13.0).round() * 13.0, (min_x + 1.0) * 13.0);
                             loop {
                                 painter.circle_filled(Pos2::new(x, y), 0.5, Color32::from_gray(90));
                                 //painter.rect_filled(Rect::from_min_max(Pos2::new(x, y), Pos2::new(x+0.5, y+0.5)), 0.0, Color32::from_gray(90));
-                                if x > f32::min(
-                                    clip_rect.right(),
-                                    (border_pos.1.1.saturating_sub(self.scene_offset.0)) as f32 * 13.0,
-                                ) {
+                                if x > f32::min(clip_rect.right(), max_x * 13.0) {
                                     break;
                                 };
                                 x += 13.0;
                             }
-                            if y > f32::min(
-                                clip_rect.bottom(),
-                                (border_pos.1.1.saturating_sub(self.scene_offset.1)) as f32 * 17.0,
-                            ) {
+                            if y > f32::min(clip_rect.bottom(), max_y * 17.0) {
                                 break;
                             };
                             y += 17.0;
@@ -1346,97 +2259,29 @@ impl App {
 
                 // Border lines
                 {
-                    puffin::profile_scope!("border"); 
+                    puffin::profile_scope!("border");
 
-                    let border_pos = self.settings.befunge_version.border_positions();
+                    let stroke = Stroke::new(1.0, Color32::from_gray(50));
+                    let left = f32::max(clip_rect.left(), min_x * 13.0 - 1.0);
+                    let right = f32::min(clip_rect.right(), (max_x + 1.0) * 13.0);
+                    let top = f32::max(clip_rect.top(), min_y * 17.0 - 1.0);
+                    let bottom = f32::min(clip_rect.bottom(), (max_y + 1.0) * 17.0);
 
                     // Top line
-                    painter.line_segment(
-                        [
-                            Pos2::new(
-                                f32::max(
-                                    clip_rect.left(),
-                                    -1.0 - (self.scene_offset.0 as f32) * 13.0,
-                                ),
-                                -0.5 - (self.scene_offset.1 as f32) * 17.0,
-                            ),
-                            Pos2::new(
-                                f32::min(
-                                    clip_rect.right(),
-                                    ((border_pos.1.1.saturating_sub(self.scene_offset.0)) as f32 + 1.0)
-                                        * 13.0,
-                                ),
-                                -0.5 - (self.scene_offset.1 as f32) * 17.0,
-                            ),
-                        ],
-                        Stroke::new(1.0, Color32::from_gray(50)),
-                    );
+                    let y = min_y * 17.0 - 0.5;
+                    painter.line_segment([Pos2::new(left, y), Pos2::new(right, y)], stroke);
 
                     // Bottom line
-                    painter.line_segment(
-                        [
-                            Pos2::new(
-                                f32::max(
-                                    clip_rect.left(),
-                                    -1.0 - (self.scene_offset.0 as f32) * 13.0,
-                                ),
-                                0.5 - ((self.scene_offset.1 - border_pos.1.1 - 1) as f32) * 17.0,
-                            ),
-                            Pos2::new(
-                                f32::min(
-                                    clip_rect.right(),
-                                    ((border_pos.1.1.saturating_sub(self.scene_offset.0)) as f32 + 1.0)
-                                        * 13.0,
-                                ),
-                                0.5 - ((self.scene_offset.1 - border_pos.1.1 - 1) as f32) * 17.0,
-                            ),
-                        ],
-                        Stroke::new(1.0, Color32::from_gray(50)),
-                    );
+                    let y = (max_y + 1.0) * 17.0 + 0.5;
+                    painter.line_segment([Pos2::new(left, y), Pos2::new(right, y)], stroke);
 
                     // Left line
-                    painter.line_segment(
-                        [
-                            Pos2::new(
-                                -0.5 - (self.scene_offset.0 as f32) * 13.0,
-                                f32::max(
-                                    clip_rect.top(),
-                                    -1.0 - (self.scene_offset.1 as f32) * 17.0,
-                                ),
-                            ),
-                            Pos2::new(
-                                -0.5 - (self.scene_offset.0 as f32) * 13.0,
-                                f32::min(
-                                    clip_rect.bottom(),
-                                    ((border_pos.1.1.saturating_sub(self.scene_offset.1)) as f32 + 1.0)
-                                        * 17.0,
-                                ),
-                            ),
-                        ],
-                        Stroke::new(1.0, Color32::from_gray(50)),
-                    );
+                    let x = min_x * 13.0 - 0.5;
+                    painter.line_segment([Pos2::new(x, top), Pos2::new(x, bottom)], stroke);
 
                     // Right line
-                    painter.line_segment(
-                        [
-                            Pos2::new(
-                                0.5 - ((self.scene_offset.0 - border_pos.1.1 - 1) as f32) * 13.0,
-                                f32::max(
-                                    clip_rect.top(),
-                                    -1.0 - (self.scene_offset.1 as f32) * 17.0,
-                                ),
-                            ),
-                            Pos2::new(
-                                0.5 - ((self.scene_offset.0 - border_pos.1.1 - 1) as f32) * 13.0,
-                                f32::min(
-                                    clip_rect.bottom(),
-                                    ((border_pos.1.1.saturating_sub(self.scene_offset.1)) as f32 + 1.0)
-                                        * 17.0,
-                                ),
-                            ),
-                        ],
-                        Stroke::new(1.0, Color32::from_gray(50)),
-                    );
+                    let x = (max_x + 1.0) * 13.0 + 0.5;
+                    painter.line_segment([Pos2::new(x, top), Pos2::new(x, bottom)], stroke);
 
                     const SHOW_OFFSET: bool = false;
                     if SHOW_OFFSET {
@@ -1461,33 +2306,63 @@ impl App {
                 {
                     puffin::profile_scope!("history heatmap");
                     match &mut self.mode {
-                        Mode::Playing { bf_state, .. } => {
+                        Mode::Playing {
+                            bf_state,
+                            error_state,
+                            snapshot,
+                            ..
+                        } => {
                             // TODO: move this somewhere more sensible
                             let now = Instant::now();
-                            bf_state
-                                .pos_history()
-                                .retain(|_, v| v.time_since(now) < Duration::from_millis(5000));
+                            let trail_duration =
+                                Duration::from_millis(self.settings.trail_duration_ms);
+                            let fade = |elapsed| calculate_decay(elapsed, trail_duration);
+                            // trails are dropped once they've faded out, and disabled histories
+                            // are cleared straight away rather than left to fade
+                            if self.settings.pos_history.0 {
+                                bf_state
+                                    .pos_history()
+                                    .retain(|_, v| fade(v.time_since(now)).is_some());
+                            } else {
+                                bf_state.pos_history().clear();
+                            }
 
-                            bf_state
-                                .put_history()
-                                .retain(|_, v| v.elapsed() < Duration::from_millis(5000));
+                            if self.settings.put_history.0 {
+                                bf_state
+                                    .put_history()
+                                    .retain(|_, v| fade(v.elapsed()).is_some());
+                            } else {
+                                bf_state.put_history().clear();
+                            }
 
-                            bf_state
-                                .get_history()
-                                .retain(|_, v| v.elapsed() < Duration::from_millis(5000));
+                            if self.settings.get_history.0 {
+                                bf_state
+                                    .get_history()
+                                    .retain(|_, v| fade(v.elapsed()).is_some());
+                            } else {
+                                bf_state.get_history().clear();
+                            }
 
+                            // ringed with the colour of the op it's on, string mode pushes
+                            // the cell rather than running it so that stays plain
+                            let ip = bf_state.cursor_position();
+                            let ring = u8::try_from(bf_state.get(ip))
+                                .ok()
+                                .filter(|_| !bf_state.string_mode())
+                                .and_then(get_color_of_bf_op)
+                                .map_or(Stroke::NONE, |color| Stroke::new(1.5, color));
                             painter.rect(
-                                recter(bf_state.cursor_position(), self.scene_offset).shrink(1.0),
+                                recter(ip, self.scene_offset).shrink(1.0),
                                 0.0,
-                                Color32::PURPLE,
-                                Stroke::NONE,
-                                StrokeKind::Outside,
+                                IP_COLOR,
+                                ring,
+                                StrokeKind::Inside,
                             );
 
                             for (pos, visited) in bf_state.pos_history() {
-                                let time = (visited.time_since(now).as_millis() as f32) / 1000.0;
-
-                                if let Some(mult) = calculate_decay(time) {
+                                if let Some(mult) =
+                                    fade(visited.time_since(now))
+                                {
                                     let rect = recter(*pos, self.scene_offset);
                                     let pos = poss((
                                         (pos.0 - self.scene_offset.0) as f32,
@@ -1504,10 +2379,8 @@ impl App {
                                     );
 
                                     if visited.wawa.north()
-                                        && let Some(mult) = calculate_decay(
-                                            now.duration_since(visited.north).as_millis() as f32
-                                                / 1000.0,
-                                        )
+                                        && let Some(mult) = fade(
+                                            now.duration_since(visited.north))
                                     {
                                         painter.rect(
                                             Rect::from_min_max(
@@ -1522,10 +2395,8 @@ impl App {
                                     }
 
                                     if visited.wawa.south()
-                                        && let Some(mult) = calculate_decay(
-                                            now.duration_since(visited.south).as_millis() as f32
-                                                / 1000.0,
-                                        )
+                                        && let Some(mult) = fade(
+                                            now.duration_since(visited.south))
                                     {
                                         painter.rect(
                                             Rect::from_min_max(
@@ -1540,10 +2411,8 @@ impl App {
                                     }
 
                                     if visited.wawa.east()
-                                        && let Some(mult) = calculate_decay(
-                                            now.duration_since(visited.east).as_millis() as f32
-                                                / 1000.0,
-                                        )
+                                        && let Some(mult) = fade(
+                                            now.duration_since(visited.east))
                                     {
                                         painter.rect(
                                             Rect::from_min_max(
@@ -1558,10 +2427,8 @@ impl App {
                                     }
 
                                     if visited.wawa.west()
-                                        && let Some(mult) = calculate_decay(
-                                            now.duration_since(visited.west).as_millis() as f32
-                                                / 1000.0,
-                                        )
+                                        && let Some(mult) = fade(
+                                            now.duration_since(visited.west))
                                     {
                                         painter.rect(
                                             Rect::from_min_max(
@@ -1578,8 +2445,7 @@ impl App {
                             }
 
                             for (pos, instant) in bf_state.put_history() {
-                                let time = (instant.elapsed().as_millis() as f32) / 1000.0;
-                                if let Some(mult) = calculate_decay(time) {
+                                if let Some(mult) = fade(instant.elapsed()) {
                                     let rect = recter(*pos, self.scene_offset);
 
                                     let [r, g, b] = self.settings.put_history.1;
@@ -1594,8 +2460,7 @@ impl App {
                             }
 
                             for (pos, instant) in bf_state.get_history() {
-                                let time = (instant.elapsed().as_millis() as f32) / 1000.0;
-                                if let Some(mult) = calculate_decay(time) {
+                                if let Some(mult) = fade(instant.elapsed()) {
                                     let rect = recter(*pos, self.scene_offset);
 
                                     let [r, g, b] = self.settings.get_history.1;
@@ -1609,15 +2474,52 @@ impl App {
                                 }
                             }
 
-                            for pos in bf_state.breakpoints().iter() {
-                                let rect = recter(*pos, self.scene_offset);
+                            if self.settings.last_op_flash.0
+                                && let Some((pos, instant)) = bf_state.last_op()
+                            {
+                                let elapsed = instant.elapsed().as_millis() as f32;
+                                let mult = 1.0 - elapsed / LAST_OP_FLASH_MS;
+                                if mult > 0.0 {
+                                    ui.ctx().request_repaint();
+                                    let [r, g, b] = self.settings.last_op_flash.1;
+                                    painter.rect(
+                                        recter(pos, self.scene_offset),
+                                        0.0,
+                                        Color32::TRANSPARENT,
+                                        Stroke::new(
+                                            2.0,
+                                            Color32::from_rgb(r, g, b).gamma_multiply(mult),
+                                        ),
+                                        StrokeKind::Inside,
+                                    );
+                                }
+                            }
+
+                            if self.settings.changed_cells.0 {
+                                let [r, g, b] = self.settings.changed_cells.1;
+                                let stroke = Stroke::new(1.5, Color32::from_rgb(r, g, b));
+                                // only written cells can differ, and writing the same value back
+                                // doesn't count as a change
+                                for pos in bf_state.written_cells() {
+                                    if bf_state.get(*pos) != snapshot.0.get(*pos) {
+                                        painter.rect(
+                                            recter(*pos, self.scene_offset),
+                                            0.0,
+                                            Color32::TRANSPARENT,
+                                            stroke,
+                                            StrokeKind::Inside,
+                                        );
+                                    }
+                                }
+                            }
 
+                            if let Some((_, pos)) = error_state {
                                 painter.rect(
-                                    rect,
+                                    recter(*pos, self.scene_offset),
                                     0.0,
-                                    Color32::TRANSPARENT,
-                                    Stroke::new(2.0, Color32::GREEN),
-                                    StrokeKind::Inside,
+                                    Color32::RED.gamma_multiply(0.4),
+                                    Stroke::new(1.0, Color32::RED),
+                                    StrokeKind::Outside,
                                 );
                             }
                         }
@@ -1651,6 +2553,47 @@ impl App {
                             );
                         }
                     };
+
+                    if let Mode::Playing { bf_state, .. } = &mut self.mode {
+                        for pos in bf_state.watchpoints().iter() {
+                            painter.rect(
+                                recter(*pos, self.scene_offset),
+                                0.0,
+                                Color32::TRANSPARENT,
+                                Stroke::new(2.0, WATCHPOINT_COLOR),
+                                StrokeKind::Inside,
+                            );
+                        }
+                    }
+
+                    let breakpoints = match &mut self.mode {
+                        Mode::Playing { bf_state, .. } => bf_state.breakpoints(),
+                        Mode::Editing { breakpoints, .. } => breakpoints,
+                    };
+                    for pos in breakpoints.iter() {
+                        let rect = recter(*pos, self.scene_offset);
+
+                        painter.rect(
+                            rect,
+                            0.0,
+                            Color32::TRANSPARENT,
+                            Stroke::new(2.0, BREAKPOINT_COLOR),
+                            StrokeKind::Inside,
+                        );
+                    }
+                }
+
+                if let Some(replay) = &self.trace_replay
+                    && let Some(entry) = replay.trace.get(replay.index)
+                {
+                    puffin::profile_scope!("trace replay");
+                    painter.rect(
+                        recter(entry.position, self.scene_offset),
+                        0.0,
+                        Color32::ORANGE.gamma_multiply(0.5),
+                        Stroke::new(1.0, Color32::ORANGE),
+                        StrokeKind::Inside,
+                    );
                 }
 
                 {
@@ -1692,25 +2635,24 @@ impl App {
                         ),
                     );
 
-                    for x in integer_clip_rect.0.0.max(0)..=integer_clip_rect.1.0 {
-                        for y in integer_clip_rect.0.1.max(0)..=integer_clip_rect.1.1 {
-                            let pos = recter((x, y), self.scene_offset);
-                            let val = match &mut self.mode {
-                        Mode::Playing { bf_state, .. } => bf_state.get((x, y)),
-                        Mode::Editing { fungespace, .. } => fungespace.get((x, y)),
-                    };
-                            if val != b' ' as i64
-                            {
-                                App::draw_char(
-                                    ui,
-                                    &self.char_renderer,
-                                    &mut mesh,
-                                    &self.settings,
-                                    pos,
-                                    val,
-                                );
-                            }
+                    let min = (integer_clip_rect.0.0.max(0), integer_clip_rect.0.1.max(0));
+                    let cells = match &self.mode {
+                        Mode::Playing { bf_state, .. } => {
+                            bf_state.cells_in_rect(min, integer_clip_rect.1)
                         }
+                        Mode::Editing { fungespace, .. } => {
+                            fungespace.cells_in_rect(min, integer_clip_rect.1)
+                        }
+                    };
+                    for (pos, val) in cells {
+                        App::draw_char(
+                            ui,
+                            &self.char_renderer,
+                            &mut mesh,
+                            &self.settings,
+                            recter(pos, self.scene_offset),
+                            val,
+                        );
                     }
 
                     ui.painter().add(egui::Shape::Mesh(mesh.into()));
@@ -1760,8 +2702,9 @@ impl App {
                                 );
 
                                 let mut breakpoint = bf_state.breakpoints().contains(&popup_pos);
+                                let mut watchpoint = bf_state.watchpoints().contains(&popup_pos);
 
-                                if ui.memory(|mem| mem.focused().is_none()) {
+                                if hotkeys_enabled(ui.ctx()) {
                                     ui.input_mut(|e| {
                                         if e.consume_key(Modifiers::NONE, egui::Key::B) {
                                             if breakpoint {
@@ -1770,6 +2713,13 @@ impl App {
                                                 bf_state.breakpoints().insert(popup_pos);
                                             }
                                         }
+                                        if e.consume_key(Modifiers::NONE, egui::Key::W) {
+                                            if watchpoint {
+                                                bf_state.watchpoints().remove(&popup_pos);
+                                            } else {
+                                                bf_state.watchpoints().insert(popup_pos);
+                                            }
+                                        }
                                     });
                                 }
                                 if checkbox_with_underline(ui, &mut breakpoint, "Breakpoint")
@@ -1781,6 +2731,16 @@ impl App {
                                         bf_state.breakpoints().remove(&popup_pos);
                                     }
                                 };
+                                if checkbox_with_underline(ui, &mut watchpoint, "Watchpoint")
+                                    .on_hover_text("Pause when a put writes to this cell")
+                                    .clicked()
+                                {
+                                    if watchpoint {
+                                        bf_state.watchpoints().insert(popup_pos);
+                                    } else {
+                                        bf_state.watchpoints().remove(&popup_pos);
+                                    }
+                                };
                             }
                             Mode::Editing {
                                 fungespace,
@@ -1824,7 +2784,17 @@ impl App {
             let pos = poss_reverse(pos, self.scene_offset);
             let border_pos = self.settings.befunge_version.border_positions();
             if intersects(border_pos, pos) {
-                self.cursor_pos = pos
+                self.cursor_pos = pos;
+
+                let val = match &self.mode {
+                    Mode::Playing { bf_state, .. } => bf_state.get(pos),
+                    Mode::Editing { fungespace, .. } => fungespace.get(pos),
+                };
+                if val != b' ' as Value {
+                    response
+                        .clone()
+                        .on_hover_ui_at_pointer(|ui| cell_tooltip(ui, pos, val));
+                }
             }
         };
 
@@ -1853,74 +2823,319 @@ impl App {
         };
     }
 
-    fn menu_bar(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
-        puffin::profile_function!();
+    /// Moves the view so `pos` is in the middle of it
+    fn center_on(&mut self, pos: Position) {
+        self.scene_offset = pos;
+        self.scene_rect.set_center(poss((0.5, 0.5)));
+    }
 
-        macro_rules! shortcut {
-            ($icon:expr) => {
-                $icon.format(&SYMBOLS, ctx.os().is_mac())
-            };
+    /// Centers the view on the next (or previous) breakpoint in reading order,
+    /// wrapping around. In editing mode the cursor is moved there too
+    fn jump_to_breakpoint(&mut self, forwards: bool) {
+        // the cell in the middle of the view, which rebasing the scene doesn't move
+        let view_center = poss_reverse(self.scene_rect.center(), self.scene_offset);
+        let (breakpoints, from) = match &mut self.mode {
+            Mode::Playing { bf_state, .. } => (&*bf_state.breakpoints(), view_center),
+            Mode::Editing {
+                breakpoints,
+                cursor_state,
+                ..
+            } => (&*breakpoints, cursor_state.location),
+        };
+
+        // sort by row, then column
+        let mut sorted: Vec<Position> = breakpoints.iter().map(|&(x, y)| (y, x)).collect();
+        sorted.sort_unstable();
+        let from = (from.1, from.0);
+        let target = if forwards {
+            sorted.iter().find(|&&pos| pos > from).or(sorted.first())
+        } else {
+            sorted
+                .iter()
+                .rev()
+                .find(|&&pos| pos < from)
+                .or(sorted.last())
+        };
+        let Some(&(y, x)) = target else {
+            return;
+        };
+
+        if let Mode::Editing { cursor_state, .. } = &mut self.mode {
+            cursor_state.location = (x, y);
         }
+        self.center_on((x, y));
+    }
 
-        egui::MenuBar::new().ui(ui, |ui| {
-            let is_web = cfg!(target_arch = "wasm32");
-            ui.menu_button("File", |ui| {
-                if ui.button("📄 New").clicked() {
-                    self.file = None;
-                    self.mode = Mode::Editing {
-                        undos: Vec::new(),
-                        redos: Vec::new(),
-                        cursor_state: CursorState::default(),
-                        fungespace: FungeSpace::default(),
-                        stdin: String::new(),
-                    }
-                }
-                if ui.button("📂 Open").clicked() {
-                    let sender = self.text_channel.0.clone();
-                    let task = rfd::AsyncFileDialog::new().pick_file();
+    /// Notes if a newly loaded program is bigger than standard Befunge-93 allows
+    fn check_program_size(&mut self) {
+        self.oversized_notice = None;
+        if !self.settings.warn_oversized {
+            return;
+        }
+        if let Some((_, (max_x, max_y))) = self.program().bounds()
+            && (max_x >= STANDARD_SIZE.0 || max_y >= STANDARD_SIZE.1)
+        {
+            self.oversized_notice = Some((max_x + 1, max_y + 1));
+        }
+    }
 
-                    let ctx = ui.ctx().clone();
-                    execute(async move {
-                        let file = task.await;
-                        if let Some(file) = file {
-                            let text = file.read().await;
-                            let _ = sender
-                                .send((file, Some(String::from_utf8_lossy(&text).to_string())));
-                            ctx.request_repaint();
-                        }
-                    });
+    /// Removes every cell outside of standard Befunge-93's 80x25, as one undo step
+    fn clamp_to_standard_size(&mut self) {
+        let Mode::Editing {
+            fungespace,
+            undos,
+            redos,
+            ..
+        } = &mut self.mode
+        else {
+            return;
+        };
+        let outside: Vec<Position> = fungespace
+            .iter_cells()
+            .map(|(pos, _)| pos)
+            .filter(|pos| pos.0 >= STANDARD_SIZE.0 || pos.1 >= STANDARD_SIZE.1)
+            .collect();
+
+        let mut ops = vec![];
+        for pos in outside {
+            ops.push((pos, fungespace.get(pos)));
+            fungespace.set(pos, b' ' as Value);
+        }
+        if !ops.is_empty() {
+            undos.push((ops.into(), false));
+            redos.clear();
+        }
+    }
+
+    fn program(&self) -> &FungeSpace {
+        match &self.mode {
+            Mode::Editing { fungespace, .. } => fungespace,
+            Mode::Playing { snapshot, .. } => &snapshot.0,
+        }
+    }
+
+    fn program_stats(&self) -> ProgramStats {
+        let program = self.program();
+        ProgramStats {
+            cells: program.non_space_count(),
+            bounds: program.bounds(),
+            playing: match &self.mode {
+                Mode::Playing { bf_state, .. } => {
+                    Some((bf_state.stack_len(), bf_state.stdout().len()))
                 }
+                Mode::Editing { .. } => None,
+            },
+        }
+    }
 
-                let save = ui
-                    .add(egui::Button::new("💾 Save").shortcut_text(shortcut!(SHORTCUT_SAVE)))
-                    .clicked();
-                let save_as = ui
-                    .add(egui::Button::new("💾 Save As").shortcut_text(shortcut!(SHORTCUT_SAVE_AS)))
-                    .clicked();
-                let reload = ui
-                    .add_enabled(
-                        matches!(self.file, Some(File::Handle(_))),
-                        egui::Button::new(icon!(icons::ICON_REPLAY, "Reload"))
-                            .shortcut_text(shortcut!(SHORTCUT_RELOAD_FILE)),
-                    )
-                    .clicked();
-                // on wasm FileHandles are EITHER read or write + reusing them doesn't work great
-                // anyways so don't reuse on wasm
-                let can_refresh_save =
-                    matches!(self.file, Some(File::Handle(_))) && cfg!(not(target_arch = "wasm32"));
+    fn is_dirty(&self) -> bool {
+        self.program().dirty
+    }
 
-                if save_as || (save && !can_refresh_save) {
-                    let sender = self.text_channel.0.clone();
+    fn has_unsaved_changes(&self) -> bool {
+        self.is_dirty() && !self.program().is_empty()
+    }
+
+    /// Runs `action`, asking for confirmation first if it would throw away unsaved changes
+    fn discard_and(&mut self, action: DiscardAction, ctx: &egui::Context) {
+        if self.has_unsaved_changes() {
+            self.open_modal = Some(ModalState::ConfirmDiscard {
+                action,
+                stats: self.program_stats(),
+                confirmed: false,
+            });
+        } else {
+            self.run_discard_action(action, ctx);
+        }
+    }
+
+    fn run_discard_action(&mut self, action: DiscardAction, ctx: &egui::Context) {
+        match action {
+            DiscardAction::New => {
+                self.file = None;
+                self.oversized_notice = None;
+                self.mode = Mode::Editing {
+                    undos: Vec::new(),
+                    redos: Vec::new(),
+                    cursor_state: CursorState::default(),
+                    fungespace: FungeSpace::default(),
+                    stdin: String::new(),
+                    breakpoints: HashSet::default(),
+                }
+            }
+            DiscardAction::Open => {
+                let sender = self.text_channel.0.clone();
+                let task = rfd::AsyncFileDialog::new().pick_file();
+
+                let ctx = ctx.clone();
+                execute(async move {
+                    let file = task.await;
+                    if let Some(file) = file {
+                        let text = file.read().await;
+                        let _ =
+                            sender.send((file, Some(String::from_utf8_lossy(&text).to_string())));
+                        ctx.request_repaint();
+                    }
+                });
+            }
+            DiscardAction::Paste => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::RequestPaste);
+                self.paste_requested = Some(ctx.cumulative_frame_nr());
+                ctx.request_repaint();
+            }
+            DiscardAction::Preset(file) => {
+                self.file = Some(File::Filename(
+                    file.path()
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string(),
+                ));
+                self.mode = Mode::Editing {
+                    undos: Vec::new(),
+                    redos: Vec::new(),
+                    cursor_state: CursorState::default(),
+                    fungespace: FungeSpace::new_from_string(
+                        file.contents_utf8().unwrap(),
+                        self.settings.loaded_tab_width(),
+                    ),
+                    stdin: String::new(),
+                    breakpoints: HashSet::default(),
+                };
+                self.check_program_size();
+            }
+            DiscardAction::UserPreset(name, program) => {
+                self.file = Some(File::Filename(name));
+                self.mode = Mode::Editing {
+                    undos: Vec::new(),
+                    redos: Vec::new(),
+                    cursor_state: CursorState::default(),
+                    fungespace: FungeSpace::new_from_string(
+                        &program,
+                        self.settings.loaded_tab_width(),
+                    ),
+                    stdin: String::new(),
+                    breakpoints: HashSet::default(),
+                };
+                self.check_program_size();
+            }
+        }
+    }
+
+    /// The program as text, honouring the "save while running" choice
+    fn serialize_program(&mut self) -> String {
+        match &mut self.mode {
+            Mode::Playing { bf_state, .. } if self.settings.save_modified_field => {
+                bf_state.serialize()
+            }
+            Mode::Playing { snapshot, .. } => snapshot.0.serialize(),
+            Mode::Editing { fungespace, .. } => fungespace.serialize(),
+        }
+    }
+
+    fn serialize_for_save(&mut self) -> String {
+        let text = self.serialize_program();
+        self.settings.line_ending.apply(text)
+    }
+
+    fn menu_bar(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        puffin::profile_function!();
+
+        macro_rules! shortcut {
+            ($icon:expr) => {
+                $icon.format(&SYMBOLS, ctx.os().is_mac())
+            };
+        }
+
+        egui::MenuBar::new().ui(ui, |ui| {
+            let is_web = cfg!(target_arch = "wasm32");
+            ui.menu_button("File", |ui| {
+                if ui.button("📄 New").clicked() {
+                    self.discard_and(DiscardAction::New, ctx);
+                }
+                if ui.button("📂 Open").clicked() {
+                    self.discard_and(DiscardAction::Open, ctx);
+                }
+                if ui
+                    .button(icon!(icons::ICON_CONTENT_PASTE, "Open from clipboard"))
+                    .clicked()
+                {
+                    self.discard_and(DiscardAction::Paste, ctx);
+                }
+
+                let save = ui
+                    .add(egui::Button::new("💾 Save").shortcut_text(shortcut!(SHORTCUT_SAVE)))
+                    .clicked();
+                let save_as = ui
+                    .add(egui::Button::new("💾 Save As").shortcut_text(shortcut!(SHORTCUT_SAVE_AS)))
+                    .clicked();
+                let reload = ui
+                    .add_enabled(
+                        matches!(self.file, Some(File::Handle(_))),
+                        egui::Button::new(icon!(icons::ICON_REPLAY, "Reload"))
+                            .shortcut_text(shortcut!(SHORTCUT_RELOAD_FILE)),
+                    )
+                    .clicked();
+                if ui
+                    .add(
+                        egui::Button::new(icon!(icons::ICON_CONTENT_COPY, "Copy program"))
+                            .shortcut_text(shortcut!(SHORTCUT_COPY_PROGRAM)),
+                    )
+                    .clicked()
+                {
+                    ctx.copy_text(self.serialize_program());
+                }
+                if ui
+                    .button(icon!(icons::ICON_HTML, "Export as HTML"))
+                    .clicked()
+                {
+                    let contents = self.program().to_html();
+                    let name = match &self.file {
+                        Some(file) => format!("{}.html", file.file_name()),
+                        None => "program.html".into(),
+                    };
+                    let task = rfd::AsyncFileDialog::new()
+                        .add_filter("HTML", &["html"])
+                        .set_file_name(name)
+                        .save_file();
+
+                    let ctx = ui.ctx().clone();
+                    execute(async move {
+                        let file = task.await;
+                        if let Some(file) = file {
+                            _ = file.write(contents.as_bytes()).await;
+                            ctx.request_repaint();
+                        }
+                    });
+                }
+                if matches!(self.mode, Mode::Playing { .. }) {
+                    ui.menu_button("Save while running", |ui| {
+                        ui.radio_value(
+                            &mut self.settings.save_modified_field,
+                            false,
+                            "Original program",
+                        );
+                        ui.radio_value(
+                            &mut self.settings.save_modified_field,
+                            true,
+                            "Current (modified) field",
+                        );
+                    });
+                }
+                // on wasm FileHandles are EITHER read or write + reusing them doesn't work great
+                // anyways so don't reuse on wasm
+                let can_refresh_save =
+                    matches!(self.file, Some(File::Handle(_))) && cfg!(not(target_arch = "wasm32"));
+
+                if save_as || (save && !can_refresh_save) {
+                    let sender = self.text_channel.0.clone();
                     let mut task = rfd::AsyncFileDialog::new();
                     if let Some(file) = &self.file {
                         task = task.set_file_name(file.file_name());
                     }
 
                     let task = task.save_file();
-                    let contents = match &mut self.mode {
-                        Mode::Playing { bf_state, .. } => bf_state.serialize(),
-                        Mode::Editing { fungespace, .. } => fungespace.serialize(),
-                    };
+                    let contents = self.serialize_for_save();
 
                     let ctx = ui.ctx().clone();
                     execute(async move {
@@ -1938,10 +3153,7 @@ impl App {
                     && let Some(File::Handle(file)) = self.file.clone()
                 {
                     let sender = self.text_channel.0.clone();
-                    let contents = match &mut self.mode {
-                        Mode::Playing { bf_state, .. } => bf_state.serialize(),
-                        Mode::Editing { fungespace, .. } => fungespace.serialize(),
-                    };
+                    let contents = self.serialize_for_save();
 
                     let ctx = ui.ctx().clone();
                     execute(async move {
@@ -1972,24 +3184,57 @@ impl App {
                             .button(file.path().file_stem().unwrap().to_string_lossy())
                             .clicked()
                         {
-                            self.file = Some(File::Filename(
-                                file.path()
-                                    .file_name()
-                                    .unwrap()
-                                    .to_string_lossy()
-                                    .to_string(),
-                            ));
-                            self.mode = Mode::Editing {
-                                undos: Vec::new(),
-                                redos: Vec::new(),
-                                cursor_state: CursorState::default(),
-                                fungespace: FungeSpace::new_from_string(
-                                    file.contents_utf8().unwrap(),
-                                ),
-                                stdin: String::new(),
-                            }
+                            self.discard_and(DiscardAction::Preset(file), ctx);
                         }
                     }
+
+                    ui.separator();
+                    let mut load = None;
+                    let mut delete = None;
+                    for (index, (name, program)) in self.settings.user_presets.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            if ui.button(name).clicked() {
+                                load =
+                                    Some(DiscardAction::UserPreset(name.clone(), program.clone()));
+                            }
+                            if ui
+                                .small_button(icons::ICON_DELETE)
+                                .on_hover_text("Delete preset")
+                                .clicked()
+                            {
+                                delete = Some(index);
+                            }
+                        });
+                    }
+                    if let Some(action) = load {
+                        self.discard_and(action, ctx);
+                    }
+                    if let Some(index) = delete {
+                        self.settings.user_presets.remove(index);
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.preset_name)
+                                .hint_text("Preset name")
+                                .desired_width(100.0),
+                        );
+                        let name = self.preset_name.trim().to_owned();
+                        if ui
+                            .add_enabled(!name.is_empty(), egui::Button::new("Save"))
+                            .on_hover_text("Save the current program as a preset")
+                            .clicked()
+                        {
+                            let program = self.serialize_program();
+                            let presets = &mut self.settings.user_presets;
+                            // saving under an existing name replaces it
+                            match presets.iter_mut().find(|preset| preset.0 == name) {
+                                Some(preset) => preset.1 = program,
+                                None => presets.push((name, program)),
+                            }
+                            self.preset_name.clear();
+                        }
+                    });
                 });
 
                 if !is_web {
@@ -2005,8 +3250,23 @@ impl App {
                     ui.set_width(300.0);
 
                     match open_modal {
-                        ModalState::Settings => Self::settings_modal(ui, &mut self.settings),
+                        ModalState::Settings => Self::settings_modal(
+                            ui,
+                            &mut self.settings,
+                            &mut self.settings_before_reset,
+                        ),
                         ModalState::SetPosition(x, y) => Self::set_position_modal(ui, x, y),
+                        ModalState::ConfirmDiscard {
+                            stats, confirmed, ..
+                        } => {
+                            // has its own buttons
+                            Self::confirm_discard_modal(ui, stats, confirmed);
+                            return;
+                        }
+                        ModalState::Stats(stats) => {
+                            ui.heading("Program stats");
+                            Self::program_stats_ui(ui, stats);
+                        }
                     }
 
                     ui.add_space(32.0);
@@ -2025,10 +3285,15 @@ impl App {
                 if modal.should_close() {
                     let prev_modal = self.open_modal.take();
                     match prev_modal.unwrap() {
-                        ModalState::Settings => (),
-                        ModalState::SetPosition(x, y) => {
-                            self.scene_offset = (x, y);
-                            self.scene_rect.set_center(poss((0.5, 0.5)));
+                        ModalState::Settings => self.settings_before_reset = None,
+                        ModalState::Stats(_) => (),
+                        ModalState::SetPosition(x, y) => self.center_on((x, y)),
+                        ModalState::ConfirmDiscard {
+                            action, confirmed, ..
+                        } => {
+                            if confirmed {
+                                self.run_discard_action(action, ctx);
+                            }
                         }
                     }
                 }
@@ -2037,6 +3302,14 @@ impl App {
             ui.menu_button("Settings", |ui| {
                 ui.checkbox(&mut self.settings.pos_history.0, "Track position history");
                 ui.checkbox(&mut self.settings.skip_spaces, "Skip spaces");
+                ui.checkbox(&mut self.settings.record_trace, "Record execution trace");
+                ui.checkbox(
+                    &mut self.settings.paste_follows_direction,
+                    "Paste in cursor direction",
+                )
+                .on_hover_text(
+                    "When pasting while facing north or south, lines are pasted as columns.",
+                );
 
                 ui.menu_button("Invalid operation behaviour", |ui| {
                     ui.radio_value(
@@ -2054,11 +3327,17 @@ impl App {
                         InvalidOperationBehaviour::Ignore,
                         "Ignore",
                     );
-                });
+                })
+                .response
+                .on_hover_text("What ops that aren't recognised do. Other errors always halt.");
                 ui.checkbox(
                     &mut self.settings.render_unicode,
                     "Display non-ascii characters",
                 );
+                ui.checkbox(
+                    &mut self.settings.control_names,
+                    "Display control characters by name",
+                );
 
                 let settings_button =
                     egui::Button::new("Advanced settings").right_text(SubMenuButton::RIGHT_ARROW);
@@ -2081,6 +3360,19 @@ impl App {
                         poss(((program_size.0 + 2) as f32, (program_size.1 + 2) as f32)),
                     );
                 };
+
+                if ui
+                    .add(egui::Button::new("Next breakpoint").shortcut_text("F9"))
+                    .clicked()
+                {
+                    self.jump_to_breakpoint(true);
+                };
+                if ui
+                    .add(egui::Button::new("Previous breakpoint").shortcut_text("Shift+F9"))
+                    .clicked()
+                {
+                    self.jump_to_breakpoint(false);
+                };
             });
 
             ui.menu_button("Tools", |ui| {
@@ -2095,6 +3387,28 @@ impl App {
                 if ui.button("Set viewport position").clicked() {
                     self.open_modal = Some(ModalState::SetPosition(0, 0));
                 };
+
+                if ui.button("Program stats").clicked() {
+                    self.open_modal = Some(ModalState::Stats(self.program_stats()));
+                };
+
+                if ui.button("Replay trace").clicked() {
+                    let sender = self.trace_channel.0.clone();
+                    let task = rfd::AsyncFileDialog::new()
+                        .add_filter("CSV", &["csv"])
+                        .pick_file();
+
+                    let ctx = ui.ctx().clone();
+                    execute(async move {
+                        let file = task.await;
+                        if let Some(file) = file {
+                            let text = file.read().await;
+                            let _ =
+                                sender.send(TraceEntry::from_csv(&String::from_utf8_lossy(&text)));
+                            ctx.request_repaint();
+                        }
+                    });
+                };
             });
 
             egui::widgets::global_theme_preference_switch(ui);
@@ -2115,31 +3429,197 @@ impl App {
             };
 
             if let Mode::Playing {
-                error_state: Some(error),
+                error_state: Some((error, (x, y))),
                 ..
             } = &self.mode
             {
-                ui.label(RichText::new(error.to_string()).color(Color32::RED));
+                ui.label(RichText::new(format!("{error} at ({x}, {y})")).color(Color32::RED));
             }
 
-            if let Some(file) = &self.file {
-                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                    ui.spacing_mut().item_spacing.x = 0.0;
-                    if let Mode::Editing {
-                        fungespace: FungeSpace { dirty: true, .. },
-                        ..
-                    } = &mut self.mode
+            if let Mode::Playing { loop_detector, .. } = &mut self.mode
+                && !loop_detector.dismissed
+                && let Some((x, y)) = loop_detector.detected
+            {
+                ui.label(
+                    RichText::new(format!("Possible infinite loop at ({x}, {y})"))
+                        .color(Color32::ORANGE),
+                )
+                .on_hover_text(
+                    "The program returned to the same position, direction and stack state.",
+                );
+                if ui.small_button("Dismiss").clicked() {
+                    loop_detector.dismissed = true;
+                }
+            }
+
+            if let Mode::Playing { loop_detector, .. } = &mut self.mode
+                && !loop_detector.wander_dismissed
+                && let Some((x, y)) = loop_detector.wandering
+            {
+                ui.label(
+                    RichText::new(format!("IP left the program, now at ({x}, {y})"))
+                        .color(Color32::ORANGE),
+                )
+                .on_hover_text(format!(
+                    "The IP spent more than {} steps outside of every cell in the program.",
+                    self.settings.wander_detection.1
+                ));
+                if ui.small_button("Dismiss").clicked() {
+                    loop_detector.wander_dismissed = true;
+                }
+            }
+
+            if let Some((width, height)) = self.oversized_notice {
+                ui.label(
+                    RichText::new(format!("Program is {width}x{height}, bigger than 80x25"))
+                        .color(Color32::ORANGE),
+                )
+                .on_hover_text("Standard Befunge-93 programs must fit in 80 columns and 25 rows.");
+                if ui.small_button("Clamp to 80x25").clicked() {
+                    self.clamp_to_standard_size();
+                    self.oversized_notice = None;
+                }
+                if ui.small_button("Dismiss").clicked() {
+                    self.oversized_notice = None;
+                }
+            }
+
+            let dirty = self.is_dirty();
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.spacing_mut().item_spacing.x = 0.0;
+                if dirty {
+                    ui.label("*").on_hover_text("Unsaved changes");
+                }
+                ui.label(self.display_file_name());
+            });
+        });
+    }
+
+    fn display_file_name(&self) -> String {
+        self.file
+            .as_ref()
+            .map_or_else(|| String::from("untitled"), File::file_name)
+    }
+
+    fn update_window_title(&mut self, ctx: &egui::Context) {
+        if cfg!(target_arch = "wasm32") {
+            return;
+        }
+
+        let title = format!(
+            "{}{} - Befunge editor",
+            self.display_file_name(),
+            if self.is_dirty() { "*" } else { "" }
+        );
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+    }
+
+    fn trace_replay_window(&mut self, ctx: &egui::Context) {
+        puffin::profile_function!();
+        let Some(replay) = &mut self.trace_replay else {
+            return;
+        };
+
+        let mut open = true;
+        egui::Window::new("Trace replay")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                let Some(max_index) = replay.trace.len().checked_sub(1) else {
+                    ui.label("Trace is empty");
+                    return;
+                };
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(replay.index > 0, egui::Button::new(icons::ICON_ARROW_LEFT))
+                        .clicked()
                     {
-                        ui.label("*");
+                        replay.index -= 1;
+                    }
+                    ui.add(egui::Slider::new(&mut replay.index, 0..=max_index));
+                    if ui
+                        .add_enabled(
+                            replay.index < max_index,
+                            egui::Button::new(icons::ICON_ARROW_RIGHT),
+                        )
+                        .clicked()
+                    {
+                        replay.index += 1;
                     }
-                    ui.label(file.file_name());
                 });
-            }
-        });
+
+                let entry = replay.trace[replay.index];
+                let op = match u32::try_from(entry.op).ok().and_then(char::from_u32) {
+                    Some(chr) if !chr.is_control() => format!("{chr} ({})", entry.op),
+                    _ => entry.op.to_string(),
+                };
+                ui.label(format!("Step: {}", entry.step));
+                ui.label(format!(
+                    "Position: {}, {}",
+                    entry.position.0, entry.position.1
+                ));
+                ui.label(format!("Op: {op}"));
+                ui.label(format!("Stack depth: {}", entry.stack_depth));
+                ui.checkbox(&mut replay.follow, "Follow");
+            });
+
+        if !open {
+            self.trace_replay = None;
+        }
+    }
+
+    /// Lists breakpoints by row then column, with buttons to remove them.
+    /// Returns the one clicked on, to jump to
+    fn breakpoint_list(ui: &mut egui::Ui, breakpoints: &mut HashSet<Position>) -> Option<Position> {
+        let mut jump = None;
+        if breakpoints.is_empty() {
+            return jump;
+        }
+        egui::CollapsingHeader::new(format!("Breakpoints ({})", breakpoints.len()))
+            .id_salt("breakpoint_list")
+            .show(ui, |ui| {
+                // same order F9 goes through them in
+                let mut sorted = breakpoints.iter().copied().collect::<Vec<_>>();
+                sorted.sort_unstable_by_key(|&(x, y)| (y, x));
+                ScrollArea::vertical()
+                    .id_salt("breakpoints")
+                    .max_height(160.0)
+                    .show(ui, |ui| {
+                        for pos in sorted {
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add(
+                                        Label::new(format!("{}, {}", pos.0, pos.1))
+                                            .sense(Sense::click()),
+                                    )
+                                    .on_hover_text("Jump to breakpoint")
+                                    .clicked()
+                                {
+                                    jump = Some(pos);
+                                }
+                                if ui
+                                    .small_button(icons::ICON_DELETE)
+                                    .on_hover_text("Remove breakpoint")
+                                    .clicked()
+                                {
+                                    breakpoints.remove(&pos);
+                                }
+                            });
+                        }
+                    });
+                if ui.button("Clear all").clicked() {
+                    breakpoints.clear();
+                }
+            });
+        jump
     }
 
     fn info_panel(&mut self, ui: &mut egui::Ui) {
         puffin::profile_function!();
+        let mut center = None;
         match &mut self.mode {
             Mode::Playing {
                 bf_state, running, ..
@@ -2164,59 +3644,325 @@ impl App {
                         painter.circle(c, r, graphics.current_color, stroke);
                     });
 
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button("Clear canvas")
+                            .on_hover_text("Fill the canvas with black")
+                            .clicked()
+                        {
+                            graphics.clear();
+                            self.canvas_edited = true;
+                        }
+                        let menu = ui.menu_button("Resize canvas", |ui| {
+                            let max = Graphics::MAX_IMAGE_SIZE as usize;
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::DragValue::new(&mut self.canvas_size.0).range(1..=max),
+                                );
+                                ui.label("x");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.canvas_size.1).range(1..=max),
+                                );
+                            });
+                            if ui
+                                .button("Resize")
+                                .on_hover_text("Keeps what's drawn in the top left")
+                                .clicked()
+                            {
+                                graphics.resize(self.canvas_size.0, self.canvas_size.1);
+                                self.canvas_edited = true;
+                                ui.close();
+                            }
+                        });
+                        if menu.response.clicked() {
+                            self.canvas_size = graphics.size;
+                        }
+                    });
+
                     egui::Window::new("Graphics")
                         .min_size((1.0, 1.0))
+                        .default_size((256.0, 256.0))
                         .show(ui.ctx(), |ui| {
-                            self.texture.set(
-                                egui::ColorImage::new(
-                                    [graphics.size.0, graphics.size.1],
-                                    graphics.texture.clone(),
-                                ),
-                                egui::TextureOptions::NEAREST,
+                            // a new canvas size needs the view scaling again
+                            let resized = self.texture.size() != [graphics.size.0, graphics.size.1];
+                            // redraw at a steady rate, rather than whenever the app repaints
+                            let frame_time = Duration::from_millis(
+                                1000 / u64::from(self.settings.graphics_fps.max(1)),
                             );
+                            let since_upload = self.canvas_uploaded.elapsed();
+                            if resized || self.canvas_edited || since_upload >= frame_time {
+                                self.canvas_edited = false;
+                                self.texture.set(
+                                    egui::ColorImage::new(
+                                        [graphics.size.0, graphics.size.1],
+                                        graphics.texture.clone(),
+                                    ),
+                                    egui::TextureOptions::NEAREST,
+                                );
+                                self.canvas_uploaded = Instant::now();
+                            } else {
+                                // make sure the last changes get shown
+                                ui.ctx()
+                                    .request_repaint_after((frame_time - since_upload).into());
+                            }
+
+                            let mut reset = resized || self.graphics_scene_rect == Rect::ZERO;
+                            ui.horizontal(|ui| {
+                                let scale = &mut self.settings.graphics_scale;
+                                reset |= ui.checkbox(&mut scale.0, "Fit").changed();
+                                reset |= ui
+                                    .add_enabled(
+                                        !scale.0,
+                                        egui::DragValue::new(&mut scale.1)
+                                            .range(0.1..=64.0)
+                                            .speed(0.1)
+                                            .suffix("×"),
+                                    )
+                                    .changed();
+                                reset |= ui.button("Reset view").clicked();
+                                ui.add(
+                                    egui::DragValue::new(&mut self.settings.graphics_fps)
+                                        .range(1..=240)
+                                        .suffix(" fps"),
+                                )
+                                .on_hover_text("How often the canvas is redrawn");
+                                ui.checkbox(&mut self.settings.graphics_grid, "Grid")
+                                    .on_hover_text("Outline each pixel when zoomed in");
+                            });
 
-                            let ppp = ui.ctx().pixels_per_point();
                             let tex_size = self.texture.size_vec2();
-                            let available = ui.available_size();
+                            if reset {
+                                let (fit, scale) = self.settings.graphics_scale;
+                                self.graphics_scene_rect = if fit {
+                                    // an invalid rect makes the scene fit the canvas
+                                    Rect::ZERO
+                                } else {
+                                    Rect::from_center_size(
+                                        (tex_size / 2.0).to_pos2(),
+                                        ui.available_size() / scale,
+                                    )
+                                };
+                            }
+                            // from last frame's view, close enough to decide on the grid
+                            let zoom = ui.available_width() / self.graphics_scene_rect.width();
+                            let show_grid = self.settings.graphics_grid
+                                && zoom.is_finite()
+                                && zoom >= MIN_PIXEL_GRID_ZOOM;
+                            Scene::new().zoom_range(0.1..=64.0).show(
+                                ui,
+                                &mut self.graphics_scene_rect,
+                                |ui| {
+                                    // one point per pixel, the scene handles scaling
+                                    let (rect, canvas) =
+                                        ui.allocate_exact_size(tex_size, egui::Sense::click());
+
+                                    ui.painter().image(
+                                        self.texture.id(),
+                                        rect,
+                                        Rect::from_min_max(
+                                            Pos2::new(0.0, 0.0),
+                                            Pos2::new(1.0, 1.0),
+                                        ),
+                                        Color32::WHITE,
+                                    );
+                                    if show_grid {
+                                        paint_pixel_grid(ui, rect, zoom);
+                                    }
+                                    if canvas.clicked()
+                                        && let Some(pos) = canvas.interact_pointer_pos()
+                                    {
+                                        // the pointer is in scene space, same as `rect`
+                                        let pos = (pos - rect.min) / rect.size();
+                                        let pixel_pos = (
+                                            ((graphics.size.0 as f32 * pos.x) as i64)
+                                                .clamp(0, graphics.size.0 as i64 - 1),
+                                            ((graphics.size.1 as f32 * pos.y) as i64)
+                                                .clamp(0, graphics.size.1 as i64 - 1),
+                                        );
+                                        graphics
+                                            .event_queue
+                                            .push_back(GraphicalEvent::MouseClick(pixel_pos));
+                                    }
+                                },
+                            );
+                        });
+                };
 
-                            let scale = ((available * ppp) / tex_size).floor().min_elem().max(1.0);
-                            let display_size = tex_size * scale / ppp;
+                egui::CollapsingHeader::new("Legend")
+                    .id_salt("legend")
+                    .show(ui, |ui| {
+                        let rgb = |[r, g, b]: [u8; 3]| Color32::from_rgb(r, g, b);
+                        let settings = &self.settings;
+                        // only what's currently being drawn
+                        let entries = [
+                            (true, IP_COLOR, "Instruction pointer"),
+                            (
+                                settings.pos_history.0,
+                                rgb(settings.pos_history.1),
+                                "Visited",
+                            ),
+                            (
+                                settings.put_history.0,
+                                rgb(settings.put_history.1),
+                                "Put (p)",
+                            ),
+                            (
+                                settings.get_history.0,
+                                rgb(settings.get_history.1),
+                                "Get (g)",
+                            ),
+                            (
+                                settings.last_op_flash.0,
+                                rgb(settings.last_op_flash.1),
+                                "Last op",
+                            ),
+                            (
+                                settings.changed_cells.0,
+                                rgb(settings.changed_cells.1),
+                                "Changed",
+                            ),
+                            (
+                                !bf_state.breakpoints().is_empty(),
+                                BREAKPOINT_COLOR,
+                                "Breakpoint",
+                            ),
+                            (
+                                !bf_state.watchpoints().is_empty(),
+                                WATCHPOINT_COLOR,
+                                "Watchpoint",
+                            ),
+                        ];
+                        for (_, color, name) in entries.into_iter().filter(|entry| entry.0) {
+                            ui.horizontal(|ui| {
+                                let (rect, _) =
+                                    ui.allocate_exact_size(Vec2::splat(12.0), Sense::hover());
+                                ui.painter().rect_filled(rect, 2.0, color);
+                                ui.label(name);
+                            });
+                        }
+                    });
 
-                            let (rect, canvas) =
-                                ui.allocate_exact_size(display_size, egui::Sense::click());
+                egui::CollapsingHeader::new("Cells")
+                    .id_salt("cell_list")
+                    .show(ui, |ui| {
+                        // only gathered while the list is open
+                        let cells = bf_state.cells();
+                        ScrollArea::vertical()
+                            .id_salt("cells")
+                            .max_height(160.0)
+                            .auto_shrink([false, true])
+                            .show_rows(
+                                ui,
+                                ui.text_style_height(&TextStyle::Body),
+                                cells.len(),
+                                |ui, row_range| {
+                                    for &(pos, val) in &cells[row_range] {
+                                        let text = format!("{}, {}: {val}", pos.0, pos.1);
+                                        if ui
+                                            .add(Label::new(text).sense(Sense::click()))
+                                            .on_hover_ui(|ui| cell_tooltip(ui, pos, val))
+                                            .clicked()
+                                        {
+                                            center = Some(pos);
+                                        }
+                                    }
+                                },
+                            );
+                    });
 
-                            let snapped_min = (rect.min * ppp).round() / ppp;
-                            let snapped_rect = egui::Rect::from_min_size(snapped_min, display_size);
+                if let Some(pos) = Self::breakpoint_list(ui, bf_state.breakpoints()) {
+                    center = Some(pos);
+                }
 
-                            let sized_texture =
-                                egui::load::SizedTexture::new(&self.texture, display_size);
-                            ui.painter().image(
-                                sized_texture.id,
-                                snapped_rect,
-                                Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(1.0, 1.0)),
-                                Color32::WHITE,
+                if bf_state.string_mode() {
+                    // same colour the editor uses for its string mode cursor
+                    ui.label(RichText::new("String mode").color(Color32::LIGHT_GREEN))
+                        .on_hover_text("Cells are being pushed as characters until the next \"");
+                }
+                let second_stack = bf_state.second_stack();
+                if !second_stack.is_empty() {
+                    egui::CollapsingHeader::new(format!("Second stack ({})", second_stack.len()))
+                        .show(ui, |ui| {
+                            ScrollArea::vertical()
+                                .id_salt("second stack")
+                                .max_height(100.0)
+                                .show(ui, |ui| {
+                                    // top first, like `}` takes them
+                                    for value in second_stack.iter().rev() {
+                                        ui.label(value.to_string());
+                                    }
+                                });
+                        })
+                        .header_response
+                        .on_hover_text("Values moved aside with {, for } to bring back");
+                }
+                let (puts, gets) = bf_state.put_get_counts();
+                ui.label(format!("Puts: {puts}  Gets: {gets}"))
+                    .on_hover_text("p and g ops run since the last reset");
+                egui::CollapsingHeader::new("Op counts").show(ui, |ui| {
+                    ui.checkbox(&mut self.settings.count_ops, "Count ops")
+                        .on_hover_text("Counts from when it's turned on, until the next reset");
+                    let counts = bf_state.op_counts();
+                    let total = counts.iter().sum::<u64>();
+                    let mut ops = (0..128u8)
+                        .filter(|&op| counts[op as usize] > 0)
+                        .collect::<Vec<_>>();
+                    ops.sort_by_key(|&op| std::cmp::Reverse(counts[op as usize]));
+                    for op in ops.into_iter().take(MAX_OP_COUNTS_SHOWN) {
+                        let count = counts[op as usize];
+                        let text = match control_name(op) {
+                            Some(name) => name.to_owned(),
+                            None => (op as char).to_string(),
+                        };
+                        let text = RichText::new(text).text_style(TextStyle::Monospace);
+                        ui.horizontal(|ui| {
+                            ui.label(match get_color_of_bf_op(op) {
+                                Some(color) => text.color(color),
+                                None => text,
+                            });
+                            ui.add(
+                                egui::ProgressBar::new(count as f32 / total as f32)
+                                    .text(count.to_string()),
                             );
-                            if canvas.clicked()
-                                && let Some(pos) = canvas.interact_pointer_pos()
-                            {
-                                let container = canvas.interact_rect;
-                                let pos = pos.clamp(container.min, container.max);
-                                let pos = pos2(
-                                    (pos.x - container.left()) / container.width(),
-                                    (pos.y - container.top()) / container.height(),
-                                );
-                                let pixel_pos = (
-                                    ((graphics.size.0 - 1) as f32 * pos.x).round() as i64,
-                                    ((graphics.size.1 - 1) as f32 * pos.y).round() as i64,
-                                );
-                                graphics
-                                    .event_queue
-                                    .push_back(GraphicalEvent::MouseClick(pixel_pos));
-                            }
+                        })
+                        .response
+                        .on_hover_text(op_name(op).unwrap_or("Not an op"));
+                    }
+                });
+                let underflows = bf_state.stack_underflows();
+                if underflows > 0 {
+                    ui.label(
+                        RichText::new(format!("Stack underflows: {underflows}"))
+                            .color(ui.visuals().warn_fg_color),
+                    )
+                    .on_hover_text("Times an empty stack was popped, giving 0");
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Stack:");
+                    ui.menu_button("As text", |ui| {
+                        let top_first = &mut self.settings.stack_text_top_first;
+                        ui.horizontal(|ui| {
+                            ui.radio_value(top_first, true, "Top first");
+                            ui.radio_value(top_first, false, "Bottom first");
                         });
-                };
-
-                ui.label("Stack:");
+                        let text = stack_as_text(&bf_state.stack(), *top_first);
+                        ui.add(
+                            egui::TextEdit::multiline(&mut text.as_str())
+                                .font(TextStyle::Monospace)
+                                .desired_rows(4),
+                        );
+                    })
+                    .response
+                    .on_hover_text("Read the whole stack as characters");
+                    ui.checkbox(&mut self.settings.compact_stack.0, "Compact")
+                        .on_hover_text("Only show the values nearest the top of the stack");
+                    if self.settings.compact_stack.0 {
+                        ui.add(
+                            egui::DragValue::new(&mut self.settings.compact_stack.1)
+                                .range(1..=1000),
+                        );
+                    }
+                });
                 ui.with_layout(egui::Layout::bottom_up(egui::Align::LEFT), |ui| {
                     ui.add_space(2.0);
 
@@ -2227,11 +3973,95 @@ impl App {
                     {
                         *running = true
                     }
+                    if !bf_state.string_mode()
+                        && bf_state.get(bf_state.cursor_position()) == b'&' as Value
+                        && let Some(problem) = number_input_problem(bf_state.stdin())
+                    {
+                        ui.colored_label(ui.visuals().warn_fg_color, problem);
+                    }
                     ui.label("Input:");
 
                     ui.add_space(2.0);
-                    ui.label(bf_state.stdout());
-                    ui.label("Output:");
+                    let terminal = self.settings.terminal_output;
+                    let terminal_output;
+                    let output = if terminal {
+                        terminal_output = match self.settings.console_size {
+                            (true, (columns, rows)) => {
+                                console_text(bf_state.stdout(), columns, rows)
+                            }
+                            (false, _) => terminal_text(bf_state.stdout(), None),
+                        };
+                        &terminal_output
+                    } else {
+                        bf_state.stdout()
+                    };
+                    let text_style = if terminal {
+                        TextStyle::Monospace
+                    } else {
+                        TextStyle::Body
+                    };
+                    // terminal lines don't wrap, so they can be scrolled sideways instead
+                    let scroll_area = ScrollArea::new([terminal, true])
+                        .id_salt("output")
+                        .max_height(160.0)
+                        .auto_shrink([false, true])
+                        .stick_to_bottom(self.settings.output_auto_scroll);
+                    if output.len() < LARGE_OUTPUT_LENGTH {
+                        scroll_area.show(ui, |ui| {
+                            let text = RichText::new(output).text_style(text_style.clone());
+                            if terminal {
+                                ui.add(Label::new(text).extend());
+                            } else {
+                                ui.add(Label::new(text).wrap());
+                            }
+                        });
+                    } else {
+                        // too much text to lay out every frame, so only
+                        // lay out the visible lines (without wrapping)
+                        let lines = output.lines().collect::<Vec<_>>();
+                        scroll_area.show_rows(
+                            ui,
+                            ui.text_style_height(&text_style),
+                            lines.len(),
+                            |ui, row_range| {
+                                for line in &lines[row_range] {
+                                    let text = RichText::new(*line).text_style(text_style.clone());
+                                    if terminal {
+                                        ui.add(Label::new(text).extend());
+                                    } else {
+                                        ui.add(Label::new(text).truncate());
+                                    }
+                                }
+                            },
+                        );
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Output:");
+                        ui.checkbox(&mut self.settings.output_auto_scroll, "Auto-scroll");
+                    });
+                    if self.settings.terminal_output {
+                        ui.horizontal(|ui| {
+                            let (console, (columns, rows)) = &mut self.settings.console_size;
+                            ui.checkbox(console, "Console").on_hover_text(
+                                "Wrap lines and only keep the last rows, like a fixed size terminal",
+                            );
+                            let enabled = *console;
+                            ui.add_enabled(enabled, egui::DragValue::new(columns).range(1..=1000));
+                            ui.label("x");
+                            ui.add_enabled(enabled, egui::DragValue::new(rows).range(1..=1000));
+                        });
+                    }
+                    ui.checkbox(&mut self.settings.terminal_output, "Terminal style")
+                        .on_hover_text(
+                            "Fixed width text, with tabs, carriage returns and backspaces \
+                            laid out like a terminal would",
+                        );
+                    if bf_state.stdout_truncated() {
+                        ui.label(RichText::new("Older output was dropped").weak())
+                            .on_hover_text(
+                                "The output limit can be changed in the advanced settings.",
+                            );
+                    }
                     ui.add_space(2.0);
 
                     ui.vertical(|ui| {
@@ -2254,6 +4084,34 @@ impl App {
                             interact_handle_opacity: 0.8,
                             ..ScrollStyle::solid()
                         };
+                        if self.settings.compact_stack.0 {
+                            let stack = bf_state.stack();
+                            let shown = stack.len().min(self.settings.compact_stack.1);
+                            ScrollArea::vertical()
+                                .auto_shrink([false; 2])
+                                .show(ui, |ui| {
+                                    let painter = ui.painter();
+                                    painter.rect_filled(
+                                        ui.clip_rect(),
+                                        5.0,
+                                        ui.visuals().faint_bg_color,
+                                    );
+                                    // newest values first
+                                    for value in stack.iter().rev().take(shown) {
+                                        ui.label(value.to_string());
+                                    }
+                                    if stack.len() > shown {
+                                        ui.label(
+                                            RichText::new(format!(
+                                                "(+{} more)",
+                                                stack.len() - shown
+                                            ))
+                                            .weak(),
+                                        );
+                                    }
+                                });
+                            return;
+                        }
                         ScrollArea::vertical()
                             .auto_shrink([false; 2])
                             .scroll_bar_visibility(ScrollBarVisibility::AlwaysVisible)
@@ -2276,7 +4134,17 @@ impl App {
                     });
                 });
             }
-            Mode::Editing { stdin, .. } => {
+            Mode::Editing {
+                stdin,
+                breakpoints,
+                cursor_state,
+                ..
+            } => {
+                if let Some(pos) = Self::breakpoint_list(ui, breakpoints) {
+                    cursor_state.location = pos;
+                    center = Some(pos);
+                }
+
                 ui.label("Version:");
                 let version = self.settings.befunge_version;
                 if ui
@@ -2306,11 +4174,29 @@ impl App {
                 });
             }
         }
+
+        if let Some(pos) = center {
+            self.center_on(pos);
+        }
     }
 
-    fn settings_modal(ui: &mut egui::Ui, settings: &mut Settings) {
+    fn settings_modal(
+        ui: &mut egui::Ui,
+        settings: &mut Settings,
+        before_reset: &mut Option<Box<Settings>>,
+    ) {
         ui.heading("Advanced settings");
         ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("History trail duration");
+            ui.add(
+                egui::Slider::new(&mut settings.trail_duration_ms, 100..=60_000)
+                    .logarithmic(true)
+                    .suffix(" ms"),
+            )
+            .on_hover_text("How long trails take to fade out completely");
+        });
+        ui.separator();
         ui.label(RichText::new("Track position history").font(FontId::proportional(14.0)));
         ui.horizontal(|ui| {
             ui.color_edit_button_srgb(&mut settings.pos_history.1);
@@ -2334,16 +4220,209 @@ impl App {
         });
         ui.horizontal(|ui| ui.checkbox(&mut settings.get_history.0, "Enabled"));
 
+        ui.separator();
+        ui.label(RichText::new("Flash last executed op").font(FontId::proportional(14.0)));
+        ui.horizontal(|ui| {
+            ui.color_edit_button_srgb(&mut settings.last_op_flash.1);
+            ui.label("Color");
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.last_op_flash.0, "Enabled")
+                .on_hover_text("Adds some drawing cost at high speeds");
+        });
+
+        ui.separator();
+        ui.label(RichText::new("Highlight changed cells").font(FontId::proportional(14.0)));
+        ui.horizontal(|ui| {
+            ui.color_edit_button_srgb(&mut settings.changed_cells.1);
+            ui.label("Color");
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.changed_cells.0, "Enabled")
+                .on_hover_text("Cells that differ from the field the run started with");
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Tab width");
+            ui.add(egui::DragValue::new(&mut settings.tab_width).range(1..=64));
+            ui.checkbox(&mut settings.tab_inserts_spaces, "Tab inserts spaces");
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.checkbox(
+                &mut settings.warn_oversized,
+                "Warn about programs over 80x25",
+            )
+            .on_hover_text("Standard Befunge-93 limits programs to 80 columns and 25 rows");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Save line endings");
+            ui.radio_value(&mut settings.line_ending, LineEnding::Lf, "LF");
+            ui.radio_value(&mut settings.line_ending, LineEnding::CrLf, "CRLF");
+            ui.radio_value(&mut settings.line_ending, LineEnding::Cr, "CR");
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.expand_tabs.0, "Expand tabs when loading")
+                .on_hover_text("Otherwise each tab is loaded as a cell holding a tab");
+            ui.add_enabled(
+                settings.expand_tabs.0,
+                egui::DragValue::new(&mut settings.expand_tabs.1)
+                    .range(1..=64)
+                    .prefix("width "),
+            );
+        });
+
         ui.separator();
         ui.horizontal(|ui| ui.checkbox(&mut settings.non_blocking_input, "Non-blocking input"));
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.input_bytes, "Byte IO")
+                .on_hover_text(
+                    "~ reads and , writes one UTF-8 byte at a time, like a byte oriented \
+                    interpreter, instead of a whole character",
+                );
+        });
+        ui.horizontal(|ui| {
+            ui.label("On overflow")
+                .on_hover_text("What + - and * do when the result doesn't fit in a cell");
+            ui.radio_value(&mut settings.overflow, Overflow::Wrap, "Wrap");
+            ui.radio_value(&mut settings.overflow, Overflow::Saturate, "Saturate");
+            ui.radio_value(&mut settings.overflow, Overflow::Error, "Halt");
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.strict_stack, "Strict stack")
+                .on_hover_text(
+                    "Halt with an error when popping an empty stack, instead of getting 0",
+                );
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.strict_graphics, "Strict graphics")
+                .on_hover_text(
+                    "Halt with an error on graphics ops before 's', instead of ignoring them",
+                );
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.floored_division, "Floored division")
+                .on_hover_text(
+                    "Round / and % towards negative infinity (-7 2 / gives -4, -7 2 % gives 1), \
+                    instead of towards zero (-3 and -1)",
+                );
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(
+                &mut settings.dot_trailing_space,
+                "Space after numeric output",
+            )
+            .on_hover_text("Standard Befunge-93 prints a space after each number output by '.'");
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.output_limit.0, "Limit output length");
+            ui.add_enabled(
+                settings.output_limit.0,
+                egui::DragValue::new(&mut settings.output_limit.1)
+                    .range(1_000..=usize::MAX)
+                    .suffix(" bytes"),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.stack_limit.0, "Limit stack depth")
+                .on_hover_text("Halt with an error instead of using up all the memory");
+            ui.add_enabled(
+                settings.stack_limit.0,
+                egui::DragValue::new(&mut settings.stack_limit.1)
+                    .range(1_000..=usize::MAX)
+                    .suffix(" values"),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.loop_detection.0, "Detect infinite loops");
+            ui.add_enabled(
+                settings.loop_detection.0,
+                egui::DragValue::new(&mut settings.loop_detection.1).range(2..=4096),
+            )
+            .on_hover_text("How many recent steps to compare against.\nHigher values catch longer loops, but are slower.");
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(
+                &mut settings.wander_detection.0,
+                "Detect leaving the program",
+            );
+            ui.add_enabled(
+                settings.wander_detection.0,
+                egui::DragValue::new(&mut settings.wander_detection.1)
+                    .range(1..=usize::MAX)
+                    .suffix(" steps"),
+            )
+            .on_hover_text(
+                "How long the IP can spend outside of every cell before it's pointed out",
+            );
+            ui.add_enabled(
+                settings.wander_detection.0,
+                egui::Checkbox::new(&mut settings.pause_on_wander, "Pause"),
+            );
+        });
         ui.horizontal(|ui| ui.checkbox(&mut settings.run_until_breakpoint, "Run until breakpoint (DANGER)").on_hover_text("Will freeze the UI while working.\nIf there are no breakpoints then this effectively crashes the app."));
 
         ui.separator();
-        if ui.button("Reset all settings").clicked() {
-            *settings = Settings::default();
+        // a reset can be undone until the modal is closed
+        if before_reset.is_some() {
+            if ui
+                .button("Undo reset")
+                .on_hover_text("Bring back the settings from before the reset")
+                .clicked()
+                && let Some(previous) = before_reset.take()
+            {
+                settings.undo_reset(*previous);
+            }
+        } else if ui
+            .button("Reset all settings")
+            .on_hover_text("Saved programs are kept")
+            .clicked()
+        {
+            *before_reset = Some(Box::new(settings.reset()));
         };
     }
 
+    fn confirm_discard_modal(ui: &mut egui::Ui, stats: &ProgramStats, confirmed: &mut bool) {
+        ui.heading("Discard current program?");
+        ui.label("The current program has unsaved changes, which will be lost.");
+        ui.add_space(8.0);
+        Self::program_stats_ui(ui, stats);
+
+        ui.add_space(32.0);
+
+        egui::Sides::new().show(
+            ui,
+            |_ui| {},
+            |ui| {
+                if ui.button("Discard").clicked() {
+                    *confirmed = true;
+                    ui.close();
+                }
+                if ui.button("Cancel").clicked() {
+                    ui.close();
+                }
+            },
+        );
+    }
+
+    fn program_stats_ui(ui: &mut egui::Ui, stats: &ProgramStats) {
+        ui.label(format!("Non-space cells: {}", stats.cells));
+        match stats.bounds {
+            Some(((min_x, min_y), (max_x, max_y))) => ui.label(format!(
+                "Bounds: ({min_x}, {min_y}) to ({max_x}, {max_y}), {}x{}",
+                max_x - min_x + 1,
+                max_y - min_y + 1
+            )),
+            None => ui.label("Bounds: empty"),
+        };
+        if let Some((stack_len, output_len)) = stats.playing {
+            ui.label(format!("Stack size: {stack_len}"));
+            ui.label(format!("Output length: {output_len}"));
+        }
+    }
+
     fn set_position_modal(ui: &mut egui::Ui, x: &mut i64, y: &mut i64) {
         ui.heading("Set position");
         ui.add(egui::DragValue::new(x).speed(0.1));
@@ -2359,7 +4438,20 @@ impl App {
         val: i64,
     ) {
         if let Ok(val) = TryInto::<u8>::try_into(val) {
-            if val < b' ' {
+            if val < b' '
+                && settings.control_names
+                && let Some(name) = control_name(val)
+            {
+                puffin::profile_scope_if!(PROFILE_EACH_CHAR, "char control name");
+                char_renderer.draw(mesh, pos, b' ', Color32::GRAY);
+                ui.painter().text(
+                    pos.center(),
+                    egui::Align2::CENTER_CENTER,
+                    name,
+                    FontId::monospace(if name.len() > 2 { 5.0 } else { 7.0 }),
+                    Color32::GRAY,
+                );
+            } else if val < b' ' {
                 puffin::profile_scope_if!(PROFILE_EACH_CHAR, "char boxed");
                 char_renderer.draw(
                     mesh,
@@ -2384,7 +4476,14 @@ impl App {
             && ui.fonts_mut(|fonts| fonts.has_glyph(&egui::FontId::monospace(1.0), val))
         {
             puffin::profile_scope_if!(PROFILE_EACH_CHAR, "char unicode");
-            ui.place(pos, egui::Label::new(String::from(val)).selectable(false));
+            // painting directly is much cheaper than placing a label per cell
+            ui.painter().text(
+                pos.center(),
+                egui::Align2::CENTER_CENTER,
+                val,
+                egui::TextStyle::Body.resolve(ui.style()),
+                ui.visuals().text_color(),
+            );
         } else {
             puffin::profile_scope_if!(PROFILE_EACH_CHAR, "char unknown");
             char_renderer.draw(mesh, pos, b' ', Color32::GRAY);
@@ -2517,13 +4616,177 @@ impl App {
     }
 }
 
+/// How quickly the view catches up with a followed cursor, higher is snappier
+const FOLLOW_SMOOTHING: f32 = 12.0;
+
+/// How many manual steps can be stepped back through
+const MAX_STEP_HISTORY: usize = 1000;
+
+/// Most field writes kept for stepping back through, before the oldest steps are forgotten
+const MAX_JOURNAL_LENGTH: usize = 1_000_000;
+
+/// How many steps a frame step runs before giving up on finding a u
+const MAX_FRAME_STEPS: usize = 10_000_000;
+
+/// How long position/put/get history trails take to fade out by default
+const DEFAULT_TRAIL_DURATION_MS: u64 = 5000;
+
+/// How many screen points a canvas pixel needs before the pixel grid is drawn
+const MIN_PIXEL_GRID_ZOOM: f32 = 6.0;
+
+/// How long the last executed op stays highlighted for
+const LAST_OP_FLASH_MS: f32 = 300.0;
+
+/// Opacity position, put and get history trails start at, before fading
+const TRAIL_OPACITY: f32 = 0.7;
+
+/// Opacity of a history trail `elapsed` into a trail lasting `duration`.
+/// Fades out logarithmically from [`TRAIL_OPACITY`], speeding up as it goes,
+/// and reaches 0 right at the end; `None` once it's no longer visible
 // could optimize by caching within a frame cuz there's likely to be a lot of identical timestamps
-fn calculate_decay(time: f32) -> Option<f32> {
-    if time >= 5.0 {
+fn calculate_decay(elapsed: Duration, duration: Duration) -> Option<f32> {
+    let progress = (elapsed.as_f64() / duration.as_f64()) as f32;
+    if progress >= 1.0 {
         return None;
     }
-    let mult = f32::log2(5.0 - time) - 1.322 - 0.3;
-    if mult <= 0.0 { None } else { Some(mult) }
+    // log2 of this goes from 0 to -TRAIL_OPACITY as progress goes from 0 to 1
+    let scale = 1.0 - f32::exp2(-TRAIL_OPACITY);
+    let mult = TRAIL_OPACITY + f32::log2(1.0 - progress * scale);
+    Some(mult.max(0.0))
+}
+
+/// Draws a line between each pixel of a canvas drawn at one point per pixel
+/// in `rect`, skipping any outside the visible area
+fn paint_pixel_grid(ui: &Ui, rect: Rect, zoom: f32) {
+    let visible = ui.clip_rect().intersect(rect);
+    if !visible.is_positive() {
+        return;
+    }
+    // keep the lines a single screen point wide regardless of zoom
+    let stroke = Stroke::new(1.0 / zoom, Color32::from_white_alpha(40));
+    let painter = ui.painter();
+
+    let start = (visible.left() - rect.left()).floor() as i64;
+    let end = (visible.right() - rect.left()).ceil() as i64;
+    for x in start..=end {
+        let x = rect.left() + x as f32;
+        painter.vline(x, visible.y_range(), stroke);
+    }
+
+    let start = (visible.top() - rect.top()).floor() as i64;
+    let end = (visible.bottom() - rect.top()).ceil() as i64;
+    for y in start..=end {
+        let y = rect.top() + y as f32;
+        painter.hline(visible.x_range(), y, stroke);
+    }
+}
+
+/// Parses space separated numbers for the initial stack
+fn parse_stack(text: &str) -> Result<Vec<Value>, String> {
+    text.split_whitespace()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("{value:?} isn't a number"))
+        })
+        .collect()
+}
+
+/// Lays out output like a terminal would: tabs go to the next multiple of 8
+/// columns, and carriage returns and backspaces move back over the current line.
+/// Other control characters are shown escaped. Lines wrap after `columns`, if given
+fn terminal_text(output: &str, columns: Option<usize>) -> String {
+    let mut text = String::with_capacity(output.len());
+    let mut line: Vec<char> = vec![];
+    let mut column = 0usize;
+    let put = |text: &mut String, line: &mut Vec<char>, column: &mut usize, chr: char| {
+        if columns.is_some_and(|columns| *column >= columns) {
+            text.extend(line.drain(..));
+            text.push('\n');
+            *column = 0;
+        }
+        if *column < line.len() {
+            line[*column] = chr;
+        } else {
+            line.resize(*column, ' ');
+            line.push(chr);
+        }
+        *column += 1;
+    };
+    for chr in output.chars() {
+        match chr {
+            '\n' => {
+                text.extend(line.drain(..));
+                text.push('\n');
+                column = 0;
+            }
+            '\r' => column = 0,
+            '\x08' => column = column.saturating_sub(1),
+            '\t' => column = ((column / 8 + 1) * 8).min(columns.unwrap_or(usize::MAX)),
+            chr if chr.is_control() => {
+                for chr in chr.escape_default() {
+                    put(&mut text, &mut line, &mut column, chr);
+                }
+            }
+            chr => put(&mut text, &mut line, &mut column, chr),
+        }
+    }
+    text.extend(line);
+    text
+}
+
+/// Terminal style output in a console `columns` wide, showing only the last `rows`
+/// lines and padded out to that many so it stays the same size
+fn console_text(output: &str, columns: usize, rows: usize) -> String {
+    let text = terminal_text(output, Some(columns));
+    let lines = text.split('\n').collect::<Vec<_>>();
+    let shown = &lines[lines.len().saturating_sub(rows)..];
+    let mut text = shown.join("\n");
+    for _ in shown.len()..rows {
+        text.push('\n');
+    }
+    text
+}
+
+/// Reads each value on the stack as a character. Values that aren't printable
+/// are escaped, with `\{n}` for ones that aren't characters at all
+fn stack_as_text(stack: &[Value], top_first: bool) -> String {
+    let mut text = String::new();
+    let mut push = |val: Value| match u32::try_from(val).ok().and_then(char::from_u32) {
+        Some(chr) if !chr.is_control() => text.push(chr),
+        Some(chr @ ('\n' | '\t' | '\r')) => text.extend(chr.escape_default()),
+        _ => text += &format!("\\{{{val}}}"),
+    };
+    if top_first {
+        stack.iter().rev().copied().for_each(&mut push);
+    } else {
+        stack.iter().copied().for_each(&mut push);
+    }
+    text
+}
+
+fn cell_tooltip(ui: &mut egui::Ui, pos: Position, val: Value) {
+    ui.label(format!("Pos: {}, {}", pos.0, pos.1));
+    ui.label(format!("Value: {val} ({val:#X})"));
+    if let Some(chr) = u32::try_from(val).ok().and_then(char::from_u32)
+        && !chr.is_control()
+    {
+        ui.label(format!("Char: {chr:?}"));
+    }
+    if let Ok(val) = u8::try_from(val)
+        && let Some(name) = control_name(val)
+    {
+        ui.label(format!("Control: {name}"));
+    }
+    if let Ok(op) = u8::try_from(val)
+        && let Some(name) = op_name(op)
+    {
+        let text = RichText::new(format!("Op: {name}"));
+        ui.label(match get_color_of_bf_op(op) {
+            Some(color) => text.color(color),
+            None => text,
+        });
+    }
 }
 
 fn checkbox_with_underline(ui: &mut egui::Ui, checked: &mut bool, text: &str) -> Response {
@@ -2562,3 +4825,68 @@ fn execute<F: Future<Output = ()> + Send + 'static>(f: F) {
 fn execute<F: Future<Output = ()> + 'static>(f: F) {
     wasm_bindgen_futures::spawn_local(f);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_endings_round_trip() {
+        let program = "v @\n>.^\n";
+        for ending in [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr] {
+            let text = ending.apply(program.to_owned());
+            let fungespace = FungeSpace::new_from_string(&text, None);
+            assert_eq!(fungespace.get((1, 1)), b'.' as Value);
+            assert_eq!(fungespace.program_size(), (11, 11));
+            assert_eq!(ending.apply(fungespace.serialize()), text);
+        }
+        // a mix of them still gives one row per line
+        let mixed = FungeSpace::new_from_string("1\r\n2\r3\n4", None);
+        assert_eq!(mixed.serialize(), "1\n2\n3\n4\n");
+    }
+
+    #[test]
+    fn trails_fade_over_the_whole_duration() {
+        let duration = Duration::from_millis(5000);
+        let at = |ms| calculate_decay(Duration::from_millis(ms), duration);
+        assert_eq!(at(0), Some(TRAIL_OPACITY));
+        let mut last = TRAIL_OPACITY;
+        for ms in (500..5000).step_by(500) {
+            let mult = at(ms).unwrap();
+            assert!(0.0 < mult && mult < last, "{ms} ms");
+            last = mult;
+        }
+        assert!(at(4990).unwrap() < 0.01);
+        assert_eq!(at(5000), None);
+    }
+
+    #[test]
+    fn points_map_to_the_cell_they_are_in() {
+        let cell = |x, y| poss_reverse(Pos2::new(x, y), (0, 0));
+        assert_eq!(cell(0.0, 0.0), (0, 0));
+        assert_eq!(cell(12.9, 16.9), (0, 0));
+        assert_eq!(cell(13.0, 17.0), (1, 1));
+        assert_eq!(cell(-0.5, -0.5), (-1, -1));
+        // exactly on a negative edge is the cell starting there
+        assert_eq!(cell(-13.0, -17.0), (-1, -1));
+        assert_eq!(cell(-26.0, -34.0), (-2, -2));
+        assert_eq!(poss_reverse(Pos2::new(-13.0, 17.0), (100, -100)), (99, -99));
+    }
+
+    #[test]
+    fn reset_keeps_saved_programs() {
+        let mut settings = Settings {
+            run_steps: 7,
+            user_presets: vec![("a".into(), "@".into())],
+            ..Default::default()
+        };
+        let previous = settings.reset();
+        assert_eq!(settings.run_steps, Settings::default().run_steps);
+        assert_eq!(settings.user_presets, [("a".into(), "@".into())]);
+
+        settings.user_presets.push(("b".into(), "1.@".into()));
+        settings.undo_reset(previous);
+        assert_eq!(settings.run_steps, 7);
+        assert_eq!(settings.user_presets.len(), 2);
+    }
+}
@@ -11,12 +11,28 @@ use enum_dispatch::enum_dispatch;
 use rand_derive2::RandGen;
 use strum_macros::EnumDiscriminants;
 
-use crate::{app::Settings, befunge93, befunge93mini};
+use crate::{
+    app::{self, InvalidOperationBehaviour, Settings},
+    befunge93, befunge93mini,
+};
 
 pub type Position = (i64, i64);
 pub type Value = i64;
 
-#[derive(RandGen, Default, Clone, Copy, PartialEq, PartialOrd, Ord, Eq, Hash, Debug)]
+#[derive(
+    RandGen,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    PartialOrd,
+    Ord,
+    Eq,
+    Hash,
+    Debug,
+    serde::Deserialize,
+    serde::Serialize,
+)]
 pub enum Direction {
     North,
     South,
@@ -88,12 +104,71 @@ impl Direction {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEntry {
+    pub step: usize,
+    pub position: Position,
+    pub op: Value,
+    pub stack_depth: usize,
+}
+
+impl TraceEntry {
+    pub const MAX_TRACE_LENGTH: usize = 1_000_000;
+    pub const CSV_HEADER: &str = "step,x,y,op,stack_depth";
+
+    pub fn to_csv(trace: &[TraceEntry]) -> String {
+        let mut out = String::from(Self::CSV_HEADER);
+        out.push('\n');
+        for entry in trace {
+            out += &format!(
+                "{},{},{},{},{}\n",
+                entry.step, entry.position.0, entry.position.1, entry.op, entry.stack_depth
+            );
+        }
+        out
+    }
+
+    pub fn from_csv(input: &str) -> Option<Vec<TraceEntry>> {
+        let mut trace = vec![];
+        for line in input.lines() {
+            if line.trim().is_empty() || line.starts_with(Self::CSV_HEADER) {
+                continue;
+            }
+            let mut fields = line.split(',').map(str::trim);
+            let entry = TraceEntry {
+                step: fields.next()?.parse().ok()?,
+                position: (fields.next()?.parse().ok()?, fields.next()?.parse().ok()?),
+                op: fields.next()?.parse().ok()?,
+                stack_depth: fields.next()?.parse().ok()?,
+            };
+            trace.push(entry);
+        }
+        Some(trace)
+    }
+}
+
+/// Execution state captured by [`Befunge::snapshot`]. The field isn't copied,
+/// instead writes to it are journaled and undone on restore
+#[derive(Clone)]
+pub struct StateSnapshot {
+    pub instruction_count: usize,
+    pub position: Position,
+    pub direction: Direction,
+    pub string_mode: bool,
+    pub stack: Vec<Value>,
+    pub second_stack: Vec<Value>,
+    pub output: String,
+    pub input: String,
+    pub pending_bytes: Vec<u8>,
+    pub(crate) journal_len: usize,
+}
+
 #[derive(Debug)]
 pub enum StepStatus {
     Normal,
     NormalNoStep,
     Breakpoint,
-    Error(&'static str),
+    Error(String),
     SyncFrame,
 }
 
@@ -115,6 +190,8 @@ pub struct Graphics {
 
 impl Graphics {
     pub const MAX_IMAGE_SIZE: i64 = 10000;
+    pub const MAX_LINE_WIDTH: i64 = 64;
+    pub const MAX_TEXT_LENGTH: i64 = 256;
     pub fn new(x: usize, y: usize) -> Self {
         Self {
             size: (x, y),
@@ -125,15 +202,16 @@ impl Graphics {
     }
 
     pub fn pixel(&mut self, x: i64, y: i64) -> StepStatus {
+        let out_of_bounds = || StepStatus::Error(format!("Pixel out of bounds ({x}, {y})"));
         let Ok(y): Result<usize, _> = y.try_into() else {
-            return StepStatus::Error("Out of bounds graphical operation");
+            return out_of_bounds();
         };
         let Ok(x): Result<usize, _> = x.try_into() else {
-            return StepStatus::Error("Out of bounds graphical operation");
+            return out_of_bounds();
         };
 
         if x >= self.size.0 || y >= self.size.1 {
-            return StepStatus::Error("Out of bounds graphical operation");
+            return out_of_bounds();
         }
 
         let index = x + y * self.size.0;
@@ -145,10 +223,268 @@ impl Graphics {
         self.texture = vec![self.current_color; self.size.0 * self.size.1];
     }
 
-    pub fn line(&mut self, x1: i32, y1: i32, x2: i32, y2: i32) {
-        // TODO: use clippin n stuff
-        for (x, y) in AnyOctant::<i32>::new((x1, y1), (x2, y2)) {
-            self.pixel(x as i64, y as i64);
+    /// Resets the canvas to black, like it was when set up, ignoring `current_color`
+    pub fn clear(&mut self) {
+        self.texture.fill(Color32::BLACK);
+    }
+
+    /// Changes the canvas size, keeping what's drawn in the top left and filling
+    /// any new space with black. Sizes are clamped like `s` would allow
+    pub fn resize(&mut self, x: usize, y: usize) {
+        let max = Self::MAX_IMAGE_SIZE as usize;
+        let (x, y) = (x.clamp(1, max), y.clamp(1, max));
+        let mut texture = vec![Color32::BLACK; x * y];
+        let width = x.min(self.size.0);
+        for row in 0..y.min(self.size.1) {
+            let old = row * self.size.0;
+            texture[row * x..row * x + width].copy_from_slice(&self.texture[old..old + width]);
+        }
+        self.texture = texture;
+        self.size = (x, y);
+    }
+
+    /// Draws a line `width` pixels thick, leaving out any part that's off the canvas
+    pub fn line(&mut self, from: Position, to: Position, width: i64) {
+        let width = width.clamp(1, Self::MAX_LINE_WIDTH);
+        // the pen covers these offsets around each point of the line
+        let low = -(width / 2);
+        let high = low + width - 1;
+        // points just off the canvas can still touch it with a thick pen
+        let Some((from, to)) = clip_line(
+            from,
+            to,
+            (-high, -high),
+            (self.size.0 as i64 - 1 - low, self.size.1 as i64 - 1 - low),
+        ) else {
+            return;
+        };
+
+        for (x, y) in AnyOctant::<i32>::new(from, to) {
+            for dy in low..=high {
+                for dx in low..=high {
+                    self.plot(x as i64 + dx, y as i64 + dy);
+                }
+            }
+        }
+    }
+
+    /// Draws `text` with its top left corner at `pos`, using a tiny 3x5 font.
+    /// Anything off the canvas is left out
+    pub fn text(&mut self, pos: Position, text: &[Value]) {
+        for (i, chr) in text.iter().enumerate() {
+            let bits = u8::try_from(*chr).map_or(glyph(0), glyph);
+            let left = pos.0.saturating_add(i as i64 * 4);
+            for row in 0..5 {
+                for column in 0..3 {
+                    if bits & (1 << (14 - row * 3 - column)) != 0 {
+                        self.plot(left.saturating_add(column), pos.1.saturating_add(row));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sets a pixel, ignoring it if it's off the canvas
+    fn plot(&mut self, x: i64, y: i64) {
+        if (0..self.size.0 as i64).contains(&x) && (0..self.size.1 as i64).contains(&y) {
+            self.texture[x as usize + y as usize * self.size.0] = self.current_color;
+        }
+    }
+}
+
+/// 3x5 bitmap of `chr` for drawing text on the canvas, one bit per pixel, rows
+/// top to bottom with the leftmost pixel as the highest bit. Lowercase letters
+/// are drawn as uppercase, anything without a glyph as a filled box
+fn glyph(chr: u8) -> u16 {
+    match chr.to_ascii_uppercase() {
+        b'0' => 0b111_101_101_101_111,
+        b'1' => 0b010_110_010_010_111,
+        b'2' => 0b111_001_111_100_111,
+        b'3' => 0b111_001_111_001_111,
+        b'4' => 0b101_101_111_001_001,
+        b'5' => 0b111_100_111_001_111,
+        b'6' => 0b111_100_111_101_111,
+        b'7' => 0b111_001_001_001_001,
+        b'8' => 0b111_101_111_101_111,
+        b'9' => 0b111_101_111_001_111,
+        b'A' => 0b010_101_111_101_101,
+        b'B' => 0b110_101_110_101_110,
+        b'C' => 0b011_100_100_100_011,
+        b'D' => 0b110_101_101_101_110,
+        b'E' => 0b111_100_110_100_111,
+        b'F' => 0b111_100_110_100_100,
+        b'G' => 0b011_100_101_101_011,
+        b'H' => 0b101_101_111_101_101,
+        b'I' => 0b111_010_010_010_111,
+        b'J' => 0b001_001_001_101_010,
+        b'K' => 0b101_101_110_101_101,
+        b'L' => 0b100_100_100_100_111,
+        b'M' => 0b101_111_111_101_101,
+        b'N' => 0b110_101_101_101_101,
+        b'O' => 0b010_101_101_101_010,
+        b'P' => 0b110_101_110_100_100,
+        b'Q' => 0b010_101_101_110_011,
+        b'R' => 0b110_101_110_101_101,
+        b'S' => 0b011_100_010_001_110,
+        b'T' => 0b111_010_010_010_010,
+        b'U' => 0b101_101_101_101_111,
+        b'V' => 0b101_101_101_101_010,
+        b'W' => 0b101_101_111_111_101,
+        b'X' => 0b101_101_010_101_101,
+        b'Y' => 0b101_101_010_010_010,
+        b'Z' => 0b111_001_010_100_111,
+        b' ' => 0b000_000_000_000_000,
+        b'.' => 0b000_000_000_000_010,
+        b',' => 0b000_000_000_010_100,
+        b'!' => 0b010_010_010_000_010,
+        b'?' => 0b110_001_010_000_010,
+        b':' => 0b000_010_000_010_000,
+        b'-' => 0b000_000_111_000_000,
+        b'+' => 0b000_010_111_010_000,
+        b'=' => 0b000_111_000_111_000,
+        b'/' => 0b001_001_010_100_100,
+        b'(' => 0b001_010_010_010_001,
+        b')' => 0b100_010_010_010_100,
+        b'\'' => 0b010_010_000_000_000,
+        b'"' => 0b101_101_000_000_000,
+        b'*' => 0b101_010_101_000_000,
+        b'<' => 0b001_010_100_010_001,
+        b'>' => 0b100_010_001_010_100,
+        b'_' => 0b000_000_000_000_111,
+        b'#' => 0b101_111_101_111_101,
+        b'%' => 0b101_001_010_100_101,
+        _ => 0b111_111_111_111_111,
+    }
+}
+
+/// Cuts the line from `from` to `to` down to the part inside `min`..=`max`,
+/// or None if none of it is (Liang-Barsky)
+fn clip_line(
+    from: Position,
+    to: Position,
+    min: Position,
+    max: Position,
+) -> Option<((i32, i32), (i32, i32))> {
+    // keeps the maths precise, no canvas gets anywhere near this big
+    let squash = |v: i64| v.clamp(i32::MIN as i64, i32::MAX as i64) as f64;
+    let (x, y) = (squash(from.0), squash(from.1));
+    let (dx, dy) = (squash(to.0) - x, squash(to.1) - y);
+    let (mut start, mut end) = (0.0f64, 1.0f64);
+    for (p, q) in [
+        (-dx, x - min.0 as f64),
+        (dx, max.0 as f64 - x),
+        (-dy, y - min.1 as f64),
+        (dy, max.1 as f64 - y),
+    ] {
+        if p == 0.0 {
+            // parallel to this edge, and outside it
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            start = start.max(q / p);
+        } else {
+            end = end.min(q / p);
+        }
+    }
+    if start > end {
+        return None;
+    }
+
+    let at = |t: f64| ((x + t * dx).round() as i32, (y + t * dy).round() as i32);
+    Some((at(start), at(end)))
+}
+
+/// Drops the start of `output` if it has grown past the output limit, leaving
+/// some slack so the whole buffer isn't shifted on every write.
+/// Returns whether anything was dropped
+pub fn limit_output(output: &mut String, settings: &Settings) -> bool {
+    let (enabled, limit) = settings.output_limit;
+    if !enabled || output.len() <= limit.saturating_add(limit / 4) {
+        return false;
+    }
+
+    let mut cut = output.len() - limit;
+    while !output.is_char_boundary(cut) {
+        cut += 1;
+    }
+    output.drain(..cut);
+    true
+}
+
+/// Adds a byte to `output`, holding it in `pending` until it completes a UTF-8
+/// character. Bytes that can't be part of one are output as `�`
+pub fn push_output_byte(output: &mut String, pending: &mut Vec<u8>, byte: u8) {
+    pending.push(byte);
+    match std::str::from_utf8(pending) {
+        Ok(text) => output.push_str(text),
+        // wait for the rest of the character
+        Err(error) if error.error_len().is_none() => return,
+        Err(_) => output.push_str(&String::from_utf8_lossy(pending)),
+    }
+    pending.clear();
+}
+
+/// Error to halt with once a push has gone past the stack limit
+pub fn stack_overflow(settings: &Settings) -> StepStatus {
+    StepStatus::Error(format!(
+        "Stack overflow (more than {} values)",
+        settings.stack_limit.1
+    ))
+}
+
+/// Seeds are kept below this, so the seed box in the UI, which edits it as an
+/// f64, shows and takes it exactly
+pub const MAX_SEED: u64 = (1 << 53) - 1;
+
+/// A random seed for `?`, no bigger than [`MAX_SEED`]
+pub fn random_seed() -> u64 {
+    rand::random::<u64>() & MAX_SEED
+}
+
+/// Most times `k` can repeat an op in one step, so it can't freeze the UI
+pub const MAX_ITERATIONS: i64 = 1_000_000;
+
+/// What an op that isn't recognised does, depending on the invalid operation behaviour
+pub fn invalid_operation(
+    error: String,
+    direction: &mut Direction,
+    settings: &Settings,
+) -> StepStatus {
+    match settings.invalid_operation_behaviour {
+        InvalidOperationBehaviour::Halt => StepStatus::Error(error),
+        InvalidOperationBehaviour::Reflect => {
+            *direction = direction.reverse();
+            StepStatus::Normal
+        }
+        InvalidOperationBehaviour::Ignore => StepStatus::Normal,
+    }
+}
+
+/// Runs `program` with `input` as stdin until it halts, without any UI.
+/// Returns the output, or the error that stopped it
+pub fn run_to_completion(program: &str, input: &str) -> Result<String, String> {
+    let mut settings = Settings::default();
+    // nothing is drawn, so don't bother tracking anything for it
+    settings.pos_history.0 = false;
+    settings.put_history.0 = false;
+    settings.get_history.0 = false;
+    settings.output_limit.0 = false;
+    // there's no one to type more input, so running out gives -1
+    settings.non_blocking_input = true;
+
+    let mut state =
+        befunge93::State::new_from_fungespace(app::FungeSpace::new_from_string(program, None));
+    state.input_buffer = input.to_owned();
+    loop {
+        match state.step(&settings) {
+            StepStatus::Normal | StepStatus::NormalNoStep | StepStatus::SyncFrame => (),
+            // no breakpoints can be set, so this is always `@`
+            StepStatus::Breakpoint => return Ok(state.output),
+            StepStatus::Error(error) => {
+                let (x, y) = state.position;
+                return Err(format!("{error} at ({x}, {y})"));
+            }
         }
     }
 }
@@ -172,13 +508,15 @@ pub fn get_color_of_bf_op(op: u8) -> Option<Color32> {
             OpTypes::Operator
         }
 
-        b'>' | b'<' | b'^' | b'v' | b'#' | b'?' | b'_' | b'|' => OpTypes::Direction,
+        b'>' | b'<' | b'^' | b'v' | b'#' | b'?' | b'_' | b'|' | b'j' | b'k' => OpTypes::Direction,
 
         b'p' | b'g' => OpTypes::Modification,
 
+        b'{' | b'}' => OpTypes::Operator,
+
         b'&' | b'~' | b'.' | b',' | b'@' => OpTypes::IO,
 
-        b's' | b'f' | b'x' | b'c' | b'u' | b'l' | b'z' => OpTypes::Graphics,
+        b's' | b'S' | b'f' | b'x' | b'c' | b'C' | b'u' | b'l' | b'L' | b'z' => OpTypes::Graphics,
 
         // noop
         _ => OpTypes::None,
@@ -195,17 +533,149 @@ pub fn get_color_of_bf_op(op: u8) -> Option<Color32> {
     }
 }
 
+/// ASCII mnemonic of a control character, like "LF" for 10
+pub fn control_name(val: u8) -> Option<&'static str> {
+    const NAMES: [&str; 32] = [
+        "NUL", "SOH", "STX", "ETX", "EOT", "ENQ", "ACK", "BEL", "BS", "TAB", "LF", "VT", "FF",
+        "CR", "SO", "SI", "DLE", "DC1", "DC2", "DC3", "DC4", "NAK", "SYN", "ETB", "CAN", "EM",
+        "SUB", "ESC", "FS", "GS", "RS", "US",
+    ];
+    match val {
+        0..32 => Some(NAMES[val as usize]),
+        127 => Some("DEL"),
+        _ => None,
+    }
+}
+
+/// Short human readable name of what `op` does
+// TODO: make generic over the version of befunge being used
+pub fn op_name(op: u8) -> Option<&'static str> {
+    Some(match op {
+        b'0'..=b'9' => "Push number",
+        b'+' => "Add",
+        b'-' => "Subtract",
+        b'*' => "Multiply",
+        b'/' => "Divide",
+        b'%' => "Modulo",
+        b'`' => "Greater than",
+        b'"' => "Toggle string mode",
+        b'\\' => "Swap",
+        b'!' => "Logical not",
+        b':' => "Duplicate",
+        b'$' => "Discard",
+
+        b'>' => "Move east",
+        b'<' => "Move west",
+        b'^' => "Move north",
+        b'v' => "Move south",
+        b'#' => "Bridge",
+        b'?' => "Move randomly",
+        b'_' => "Horizontal if",
+        b'|' => "Vertical if",
+        b'j' => "Jump",
+        b'k' => "Iterate",
+        b'{' => "Move to second stack",
+        b'}' => "Move from second stack",
+
+        b'p' => "Put",
+        b'g' => "Get",
+
+        b'&' => "Input number",
+        b'~' => "Input character",
+        b'.' => "Output number",
+        b',' => "Output character",
+        b'@' => "Halt",
+
+        b's' => "Setup canvas",
+        b'S' => "Canvas size",
+        b'f' => "Set color",
+        b'x' => "Set pixel",
+        b'c' => "Fill canvas",
+        b'C' => "Clear canvas",
+        b'u' => "Update canvas",
+        b'l' => "Draw line",
+        b'L' => "Draw thick line",
+        b'T' => "Draw text",
+        b'z' => "Poll event",
+
+        _ => return None,
+    })
+}
+
+/// Largest area where looking up every position is cheaper than filtering
+/// every stored cell
+const MAX_LOOKUP_AREA: i64 = 128 * 128;
+
+/// Top left and bottom right corners of `cells`, if there are any
+pub fn bounding_box(
+    cells: impl IntoIterator<Item = (Position, Value)>,
+) -> Option<(Position, Position)> {
+    cells
+        .into_iter()
+        .fold(None, |bounds, ((x, y), _)| match bounds {
+            None => Some(((x, y), (x, y))),
+            Some(((min_x, min_y), (max_x, max_y))) => {
+                Some(((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y))))
+            }
+        })
+}
+
 pub trait FungeSpaceTrait {
     fn set(&mut self, pos: Position, val: Value);
     fn get(&self, pos: Position) -> Value;
     fn entries(&self) -> impl Iterator<Item = (Position, Value)>;
     fn program_size(&self) -> (i64, i64);
 
+    /// Cells that aren't spaces, for read-only consumers that don't care about
+    /// how the space is stored
+    fn iter_cells(&self) -> impl Iterator<Item = (Position, Value)> {
+        self.entries().filter(|(_, val)| *val != b' ' as Value)
+    }
+
+    /// Whether every cell is a space
+    fn is_empty(&self) -> bool {
+        self.iter_cells().next().is_none()
+    }
+
+    /// Cells that aren't spaces between `min` and `max` inclusive.
+    /// Small areas are looked up position by position, large ones filter
+    /// every stored cell, so zooming out on a sparse program stays cheap
+    fn cells_in_rect(&self, min: Position, max: Position) -> Vec<(Position, Value)> {
+        let width = max.0.saturating_sub(min.0).saturating_add(1);
+        let height = max.1.saturating_sub(min.1).saturating_add(1);
+        if width <= 0 || height <= 0 {
+            return vec![];
+        }
+
+        if width.saturating_mul(height) <= MAX_LOOKUP_AREA {
+            (min.1..=max.1)
+                .flat_map(|y| (min.0..=max.0).map(move |x| (x, y)))
+                .map(|pos| (pos, self.get(pos)))
+                .filter(|(_, val)| *val != b' ' as Value)
+                .collect()
+        } else {
+            self.iter_cells()
+                .filter(|((x, y), _)| (min.0..=max.0).contains(x) && (min.1..=max.1).contains(y))
+                .collect()
+        }
+    }
+
+    /// Number of cells that aren't spaces
+    fn non_space_count(&self) -> usize {
+        self.iter_cells().count()
+    }
+
+    /// Top left and bottom right corners of the cells that aren't spaces,
+    /// if there are any
+    fn bounds(&self) -> Option<(Position, Position)> {
+        bounding_box(self.iter_cells())
+    }
+
     // TODO: make this fallible
     fn serialize(&self) -> String {
         let height = self.program_size().1;
         let mut lines: Vec<Vec<char>> = vec![vec![]; height as usize];
-        for ((x, y), val) in self.entries() {
+        for ((x, y), val) in self.iter_cells() {
             let line = &mut lines[y as usize];
             if line.len() <= x as usize {
                 line.extend(std::iter::repeat_n(' ', x as usize - line.len()));
@@ -218,11 +688,54 @@ pub trait FungeSpaceTrait {
         }
         let mut out = String::new();
         for line in lines {
-            out += &line.iter().collect::<String>();
+            out += line.iter().collect::<String>().trim_end_matches(' ');
             out += "\n";
         }
+        // drop trailing blank rows, but keep the final newline
+        out.truncate(out.trim_end_matches('\n').len());
+        out.push('\n');
         out
     }
+
+    /// Renders the field as an HTML table, with ops coloured like in the editor.
+    /// Cells that aren't printable ASCII show their value in hex
+    fn to_html(&self) -> String {
+        let mut out = String::from(
+            "<style>.befunge td { width: 1ch; padding: 0 1px; text-align: center }</style>\n\
+             <table class=\"befunge\" style=\"border-collapse: collapse; \
+             font-family: monospace; background: #1b1b1b; color: #a0a0a0\">\n",
+        );
+        let Some((_, (max_x, max_y))) = self.bounds() else {
+            return out + "</table>\n";
+        };
+        for y in 0..=max_y {
+            out += "<tr>";
+            for x in 0..=max_x {
+                let val = self.get((x, y));
+                let text = match u8::try_from(val) {
+                    Ok(b' ') => String::new(),
+                    Ok(b'<') => "&lt;".into(),
+                    Ok(b'>') => "&gt;".into(),
+                    Ok(b'&') => "&amp;".into(),
+                    Ok(chr @ b'!'..=b'~') => (chr as char).to_string(),
+                    _ => format!("<small>{val:X}</small>"),
+                };
+                match u8::try_from(val).ok().and_then(get_color_of_bf_op) {
+                    Some(color) => {
+                        out += &format!(
+                            "<td style=\"color: #{:02x}{:02x}{:02x}\">{text}</td>",
+                            color.r(),
+                            color.g(),
+                            color.b()
+                        )
+                    }
+                    None => out += &format!("<td>{text}</td>"),
+                }
+            }
+            out += "</tr>\n";
+        }
+        out + "</table>\n"
+    }
 }
 
 #[enum_dispatch]
@@ -237,10 +750,28 @@ pub trait Befunge {
     fn string_mode(&self) -> bool;
     fn cursor_position(&self) -> Position;
     fn cursor_direction(&self) -> Direction;
+    /// Where the most recently executed op was, and when it ran
+    fn last_op(&self) -> Option<(Position, Instant)>;
 
     // TODO: make this &[Value]
     fn stack(&self) -> Vec<Value>;
+    /// Replaces the stack, direction and position, for starting a run somewhere
+    /// other than the default
+    fn set_start(&mut self, stack: &[Value], direction: Direction, position: Position);
+    fn stack_len(&self) -> usize;
+    /// How many times an empty stack has been popped
+    fn stack_underflows(&self) -> usize;
+    /// How many `p` and `g` ops have run
+    fn put_get_counts(&self) -> (usize, usize);
+    /// How many times each ascii op has run, while counting ops is on
+    fn op_counts(&self) -> &[u64; 128];
+    /// Values moved aside by `{`, bottom first
+    fn second_stack(&self) -> Vec<Value>;
+    /// Value `depth` entries below the top of the stack, if there is one
+    fn stack_peek(&self, depth: usize) -> Option<Value>;
     fn stdout(&self) -> &str;
+    /// Whether older output was dropped because of the output limit
+    fn stdout_truncated(&self) -> bool;
     fn stdin(&mut self) -> &mut String;
     fn graphics(&mut self) -> Option<&mut Graphics>;
 
@@ -248,8 +779,32 @@ pub trait Befunge {
     fn get_history(&mut self) -> &mut HashMap<Position, Instant>;
     fn put_history(&mut self) -> &mut HashMap<Position, Instant>;
     fn breakpoints(&mut self) -> &mut HashSet<Position>;
+    /// Seed of the random number generator used by `?`
+    fn seed(&self) -> u64;
+    /// Restarts the random number generator used by `?` from `seed`
+    fn set_seed(&mut self, seed: u64);
+    /// Cells that aren't spaces between `min` and `max` inclusive
+    fn cells_in_rect(&self, min: Position, max: Position) -> Vec<(Position, Value)>;
+    /// Every cell that isn't a space, sorted by row then column
+    fn cells(&self) -> Vec<(Position, Value)>;
+    /// Cells that pause execution when written to with `p`
+    fn watchpoints(&mut self) -> &mut HashSet<Position>;
+    /// Cells written to since the run started, whether or not their value changed
+    fn written_cells(&self) -> &HashSet<Position>;
+    fn trace(&mut self) -> &mut Vec<TraceEntry>;
 
     fn serialize(&self) -> String;
+
+    /// Captures the state so it can be restored later, starting a journal of
+    /// field writes if there isn't one yet
+    fn snapshot(&mut self) -> StateSnapshot;
+    /// Goes back to `snapshot`. Snapshots taken after it can't be restored afterwards
+    fn restore(&mut self, snapshot: &StateSnapshot);
+    /// Drops the journal entries only snapshots older than `oldest` needed, so they
+    /// can't be restored any more. With no snapshots left, stops journaling
+    fn forget_snapshots_before(&mut self, oldest: Option<&StateSnapshot>);
+    /// How many field writes are journaled for restoring snapshots
+    fn journal_len(&self) -> usize;
 }
 
 #[derive(Clone, EnumDiscriminants)]
@@ -270,3 +825,47 @@ impl BefungeVersionDiscriminants {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_trims_blank_space() {
+        let program = app::FungeSpace::new_from_string("5 5 + .   \n   \n\n", None);
+        assert_eq!(program.serialize(), "5 5 + .\n");
+        let field = befunge93::FungeSpace::new_from_fungespace(program);
+        assert_eq!(field.serialize(), "5 5 + .\n");
+    }
+
+    #[test]
+    fn clip_line_to_canvas() {
+        let clip = |from, to| clip_line(from, to, (0, 0), (19, 19));
+        assert_eq!(clip((-5, -5), (10, 10)), Some(((0, 0), (10, 10))));
+        assert_eq!(clip((5, -10), (5, 30)), Some(((5, 0), (5, 19))));
+        assert_eq!(clip((-3, 7), (25, 7)), Some(((0, 7), (19, 7))));
+        assert_eq!(clip((3, 4), (8, 2)), Some(((3, 4), (8, 2))));
+        assert_eq!(clip((-5, -5), (-1, 30)), None);
+        assert_eq!(clip((25, 0), (30, 19)), None);
+        assert_eq!(clip((-10, 5), (5, -10)), None);
+    }
+
+    #[test]
+    fn non_space_count() {
+        let mut program = app::FungeSpace::default();
+        assert_eq!(program.non_space_count(), 0);
+        assert!(program.is_empty());
+        assert_eq!(program.bounds(), None);
+        let field = befunge93::FungeSpace::new_from_fungespace(program.clone());
+        assert_eq!(field.non_space_count(), 0);
+        assert_eq!(field.bounds(), None);
+
+        program.set((2, 3), b'@' as Value);
+        assert_eq!(program.non_space_count(), 1);
+        assert!(!program.is_empty());
+        assert_eq!(program.bounds(), Some(((2, 3), (2, 3))));
+        let field = befunge93::FungeSpace::new_from_fungespace(program);
+        assert_eq!(field.non_space_count(), 1);
+        assert_eq!(field.bounds(), Some(((2, 3), (2, 3))));
+    }
+}
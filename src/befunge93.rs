@@ -3,22 +3,67 @@ use egui::{
     Color32,
     ahash::{HashSet, HashSetExt},
 };
-use rand::Rng;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 
 use egui::ahash::HashMap;
 
 use crate::{
-    app::{self, Settings},
+    app::{self, Overflow, Settings},
     befunge::{
-        Befunge, Direction, FungeSpaceTrait, GraphicalEvent, Graphics, Position, StepStatus, Value,
-        Visited, WhereVisited,
+        Befunge, Direction, FungeSpaceTrait, GraphicalEvent, Graphics, MAX_ITERATIONS, Position,
+        StateSnapshot, StepStatus, TraceEntry, Value, Visited, WhereVisited, invalid_operation,
+        limit_output, push_output_byte, random_seed, stack_overflow,
     },
 };
 
+/// Width and height of a [`Tile`]
+const TILE_SIZE: i64 = 16;
+
+/// A square block of cells, so nearby cells share a single hashmap lookup
+#[derive(Clone)]
+struct Tile {
+    cells: [Value; (TILE_SIZE * TILE_SIZE) as usize],
+    /// Number of cells that aren't spaces, the tile is dropped when this hits 0
+    filled: u16,
+}
+
+impl Tile {
+    fn new() -> Self {
+        Self {
+            cells: [b' ' as Value; (TILE_SIZE * TILE_SIZE) as usize],
+            filled: 0,
+        }
+    }
+
+    /// Which tile `pos` is in, and its index within that tile
+    fn locate(pos: Position) -> (Position, usize) {
+        let tile = (pos.0.div_euclid(TILE_SIZE), pos.1.div_euclid(TILE_SIZE));
+        let index = pos.0.rem_euclid(TILE_SIZE) + pos.1.rem_euclid(TILE_SIZE) * TILE_SIZE;
+        (tile, index as usize)
+    }
+
+    /// Cells that aren't spaces, given the tile's own position
+    fn cells(&self, tile: Position) -> impl Iterator<Item = (Position, Value)> {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, val)| **val != b' ' as Value)
+            .map(move |(i, val)| {
+                let i = i as i64;
+                (
+                    (
+                        tile.0 * TILE_SIZE + i % TILE_SIZE,
+                        tile.1 * TILE_SIZE + i / TILE_SIZE,
+                    ),
+                    *val,
+                )
+            })
+    }
+}
+
 #[derive(Clone)]
 pub struct FungeSpace {
-    map: HashMap<Position, Value>,
-    zero_page: Box<[Value; 100]>,
+    tiles: HashMap<Position, Tile>,
     max_size: (i64, i64),
 }
 
@@ -33,11 +78,40 @@ pub struct State {
     pub get_history: HashMap<Position, Instant>,
     pub put_history: HashMap<Position, Instant>,
     pub stack: Vec<Value>,
+    /// Values moved off the stack by `{`, for `}` to bring back
+    pub second_stack: Vec<Value>,
+    pub stack_underflows: usize,
+    /// Deepest the stack can get this step, from the stack limit setting
+    stack_limit: usize,
+    /// Whether a push this step was dropped for going past `stack_limit`
+    stack_overflowed: bool,
+    /// How many `p` and `g` ops have run
+    pub put_count: usize,
+    pub op_counts: [u64; 128],
+    pub get_count: usize,
     pub output: String,
+    pub output_truncated: bool,
     pub graphics: Option<Graphics>,
     pub breakpoints: HashSet<Position>,
+    pub watchpoints: HashSet<Position>,
+    watch_hit: bool,
+    /// Every cell that has been written to this run, while changed cells are shown
+    pub written: HashSet<Position>,
+    pub trace: Vec<TraceEntry>,
+    pub last_op: Option<(Position, Instant)>,
+    /// Seed `rng` was last seeded with, so a run can be reproduced
+    pub seed: u64,
+    rng: StdRng,
+    /// Previous values of written cells, once a snapshot has been taken
+    journal: Option<Vec<(Position, Value)>>,
+    /// Entries dropped from the front of the journal, so snapshot lengths still line up
+    journal_start: usize,
     //pub input_buffer: VecDeque<i64>,
     pub input_buffer: String,
+    /// Bytes left over from a character read as UTF-8 bytes, last byte first
+    pending_bytes: Vec<u8>,
+    /// Bytes output by `,` that don't make up a whole character yet
+    pending_output: Vec<u8>,
 }
 
 impl FungeSpaceTrait for FungeSpace {
@@ -53,21 +127,38 @@ impl FungeSpaceTrait for FungeSpace {
         if pos.0 < 0 || pos.1 < 0 {
             return 0;
         }
-        if pos.0 < 10 && pos.1 < 10 {
-            self.zero_page[(pos.0 + pos.1 * 10) as usize]
-        } else {
-            *self.map.get(&pos).unwrap_or(&(b' ' as Value))
-        }
+        let (tile, index) = Tile::locate(pos);
+        self.tiles
+            .get(&tile)
+            .map_or(b' ' as Value, |tile| tile.cells[index])
     }
 
     fn entries(&self) -> impl Iterator<Item = (Position, Value)> {
-        self.map
-            .iter()
-            .map(|(k, v)| (*k, *v))
-            .chain(self.zero_page.iter().enumerate().map(|(i, val)| {
-                let i = i as i64;
-                ((i % 10, i / 10), *val)
-            }))
+        self.tiles.iter().flat_map(|(pos, tile)| tile.cells(*pos))
+    }
+
+    fn cells_in_rect(&self, min: Position, max: Position) -> Vec<(Position, Value)> {
+        if min.0 > max.0 || min.1 > max.1 {
+            return vec![];
+        }
+        let in_rect = |((x, y), _): &(Position, Value)| {
+            (min.0..=max.0).contains(x) && (min.1..=max.1).contains(y)
+        };
+
+        let (min_tile, _) = Tile::locate(min);
+        let (max_tile, _) = Tile::locate(max);
+        let tile_area = (max_tile.0 - min_tile.0 + 1).saturating_mul(max_tile.1 - min_tile.1 + 1);
+
+        if tile_area <= self.tiles.len() as i64 {
+            (min_tile.1..=max_tile.1)
+                .flat_map(|y| (min_tile.0..=max_tile.0).map(move |x| (x, y)))
+                .filter_map(|pos| Some((pos, self.tiles.get(&pos)?)))
+                .flat_map(|(pos, tile)| tile.cells(pos))
+                .filter(in_rect)
+                .collect()
+        } else {
+            self.entries().filter(in_rect).collect()
+        }
     }
 
     fn program_size(&self) -> (i64, i64) {
@@ -78,25 +169,20 @@ impl FungeSpaceTrait for FungeSpace {
 impl FungeSpace {
     pub fn new() -> Self {
         Self {
-            map: HashMap::default(),
-            zero_page: Box::new([b' '.into(); 100]),
+            tiles: HashMap::default(),
             max_size: (11, 11),
         }
     }
 
-    pub fn new_from_fungespace(mut input: app::FungeSpace) -> Self {
-        let mut zero_page = Box::new([b' '.into(); 100]);
-        let max_size = input.program_size();
-        for idx in 0..100 {
-            if let Some(val) = input.map.remove(&(idx % 10, idx / 10)) {
-                zero_page[idx as usize] = val;
-            }
-        }
-        Self {
-            map: input.map,
-            zero_page,
-            max_size,
+    pub fn new_from_fungespace(input: app::FungeSpace) -> Self {
+        let mut space = Self {
+            tiles: HashMap::default(),
+            max_size: input.program_size(),
+        };
+        for (pos, val) in input.map {
+            space.set_inner(pos, val);
         }
+        space
     }
 
     fn set_inner(&mut self, pos: Position, val: Value) {
@@ -104,35 +190,44 @@ impl FungeSpace {
             return;
         };
 
-        if pos.0 < 10 && pos.1 < 10 {
-            self.zero_page[(pos.0 + pos.1 * 10) as usize] = val
+        let (tile_pos, index) = Tile::locate(pos);
+        if val == b' ' as Value {
+            if let Some(tile) = self.tiles.get_mut(&tile_pos)
+                && tile.cells[index] != b' ' as Value
+            {
+                tile.cells[index] = val;
+                tile.filled -= 1;
+                if tile.filled == 0 {
+                    self.tiles.remove(&tile_pos);
+                }
+            }
         } else {
-            if val == b' ' as Value {
-                self.map.remove(&pos);
-            } else {
-                self.map.insert(pos, val);
+            let tile = self.tiles.entry(tile_pos).or_insert_with(Tile::new);
+            if tile.cells[index] == b' ' as Value {
+                tile.filled += 1;
             }
+            tile.cells[index] = val;
+        }
 
-            if pos.0 > self.max_size.0 {
-                self.max_size.0 = pos.0
-            }
-            if pos.1 > self.max_size.1 {
-                self.max_size.1 = pos.1
-            }
-        };
+        if pos.0 > self.max_size.0 {
+            self.max_size.0 = pos.0
+        }
+        if pos.1 > self.max_size.1 {
+            self.max_size.1 = pos.1
+        }
     }
 
+    /// Like `get`, but `None` for empty cells
     pub fn get_nullable(&self, pos: Position) -> Option<Value> {
-        if pos.0 < 10 && pos.1 < 10 {
-            Some(self.zero_page[usize::try_from(pos.0 + pos.1 * 10).unwrap()])
-        } else {
-            self.map.get(&pos).copied()
-        }
+        let (tile, index) = Tile::locate(pos);
+        let val = self.tiles.get(&tile)?.cells[index];
+        (val != b' ' as Value).then_some(val)
     }
 }
 
 impl Default for State {
     fn default() -> Self {
+        let seed = random_seed();
         Self {
             instruction_count: 0,
             map: FungeSpace::new(),
@@ -143,18 +238,81 @@ impl Default for State {
             put_history: HashMap::default(),
             get_history: HashMap::default(),
             stack: Vec::new(),
+            second_stack: Vec::new(),
+            stack_underflows: 0,
+            stack_limit: usize::MAX,
+            stack_overflowed: false,
+            put_count: 0,
+            op_counts: [0; 128],
+            get_count: 0,
             output: String::new(),
+            output_truncated: false,
             graphics: None,
             breakpoints: HashSet::new(),
+            watchpoints: HashSet::new(),
+            watch_hit: false,
+            written: HashSet::new(),
+            trace: Vec::new(),
+            last_op: None,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            journal: None,
+            journal_start: 0,
             //input_buffer: VecDeque::new(),
             input_buffer: String::new(),
+            pending_bytes: Vec::new(),
+            pending_output: Vec::new(),
         }
     }
 }
 
 impl State {
+    /// Writes to the field, journaling the old value if snapshots are in use
+    fn write(&mut self, pos: Position, val: Value) {
+        if let Some(journal) = &mut self.journal {
+            journal.push((pos, self.map.get(pos)));
+        }
+        self.map.set(pos, val);
+    }
+
+    /// All pushes go through here, so stack growth can be dealt with in one place.
+    /// Pushes past the stack limit are dropped, and the step halts with an error
+    fn push(&mut self, value: Value) {
+        if self.stack.len() >= self.stack_limit {
+            self.stack_overflowed = true;
+            return;
+        }
+        self.stack.push(value);
+    }
+
+    /// Pops `a` then `b` and pushes `b op a`, dealing with overflow how the settings say to
+    fn arithmetic(
+        &mut self,
+        op: char,
+        settings: &Settings,
+        checked: fn(Value, Value) -> Option<Value>,
+        wrapping: fn(Value, Value) -> Value,
+        saturating: fn(Value, Value) -> Value,
+    ) -> StepStatus {
+        let a = self.pop();
+        let b = self.pop();
+        let result = match settings.overflow {
+            Overflow::Wrap => wrapping(b, a),
+            Overflow::Saturate => saturating(b, a),
+            Overflow::Error => match checked(b, a) {
+                Some(result) => result,
+                None => return StepStatus::Error(format!("Overflow ({b} {op} {a})")),
+            },
+        };
+        self.push(result);
+        StepStatus::Normal
+    }
+
     fn pop(&mut self) -> Value {
-        self.stack.pop().unwrap_or(0)
+        self.stack.pop().unwrap_or_else(|| {
+            self.stack_underflows += 1;
+            0
+        })
     }
 
     pub fn new_from_fungespace(fungespace: app::FungeSpace) -> Self {
@@ -226,22 +384,37 @@ impl State {
             Direction::West => self.position = (x - 1, y),
         }
 
+        // 0..=max_size is the torus, the same cells going either way
         if self.position.0 == -1 {
-            self.position.0 = self.map.max_size.0.saturating_add(1);
+            self.position.0 = self.map.max_size.0;
         } else if self.position.0.wrapping_sub(1) >= self.map.max_size.0 {
             self.position.0 = 0
         };
 
         if self.position.1 == -1 {
-            self.position.1 = self.map.max_size.1.saturating_add(1);
+            self.position.1 = self.map.max_size.1;
         } else if self.position.1.wrapping_sub(1) >= self.map.max_size.1 {
             self.position.1 = 0
         };
     }
 
     pub fn step(&mut self, settings: &Settings) -> StepStatus {
+        if settings.record_trace && self.trace.len() < TraceEntry::MAX_TRACE_LENGTH {
+            self.trace.push(TraceEntry {
+                step: self.instruction_count,
+                position: self.position,
+                op: self.map.get(self.position),
+                stack_depth: self.stack.len(),
+            });
+        }
+        if settings.last_op_flash.0 {
+            self.last_op = Some((self.position, Instant::recent()));
+        }
         self.instruction_count += 1;
         let status = self.step_inner(settings);
+        if std::mem::take(&mut self.watch_hit) {
+            return StepStatus::Breakpoint;
+        }
         if self.breakpoints.contains(&self.position) {
             return StepStatus::Breakpoint;
         }
@@ -262,19 +435,38 @@ impl State {
 
     fn step_inner(&mut self, settings: &Settings) -> StepStatus {
         let op = self.map.get_nullable(self.position);
+        self.stack_limit = if settings.stack_limit.0 {
+            settings.stack_limit.1
+        } else {
+            usize::MAX
+        };
+        self.stack_overflowed = false;
 
         if self.string_mode {
             let op = op.unwrap_or(b' ' as Value);
             if op == b'"' as Value {
                 self.string_mode = false;
             } else {
-                self.stack.push(op);
+                self.push(op);
+                if std::mem::take(&mut self.stack_overflowed) {
+                    return stack_overflow(settings);
+                }
             }
             self.step_position(settings);
             StepStatus::Normal
         } else if let Some(op) = op {
-            if let Ok(op) = op.try_into() {
+            if let Ok(op) = u8::try_from(op) {
+                if settings.count_ops && op < 128 {
+                    self.op_counts[op as usize] += 1;
+                }
+                let underflows = self.stack_underflows;
                 let status = self.do_op(op, settings);
+                if settings.strict_stack && self.stack_underflows > underflows {
+                    return StepStatus::Error(format!("Stack underflow on {:?}", op as char));
+                }
+                if std::mem::take(&mut self.stack_overflowed) {
+                    return stack_overflow(settings);
+                }
                 match status {
                     StepStatus::Normal | StepStatus::SyncFrame => {
                         self.step_position(settings);
@@ -283,7 +475,15 @@ impl State {
                 };
                 status
             } else {
-                StepStatus::Error("Invalid operation")
+                let status = invalid_operation(
+                    format!("Invalid operation {op}"),
+                    &mut self.direction,
+                    settings,
+                );
+                if let StepStatus::Normal = status {
+                    self.step_position(settings);
+                }
+                status
             }
         } else {
             self.step_position(settings);
@@ -295,61 +495,83 @@ impl State {
         match op {
             b'"' => self.string_mode = true,
 
-            b'0'..=b'9' => self.stack.push((op - b'0').into()),
+            b'0'..=b'9' => self.push((op - b'0').into()),
 
             // 2 op operations
             b'+' => {
-                let a = self.pop();
-                let b = self.pop();
-                self.stack.push(b + a);
+                return self.arithmetic(
+                    '+',
+                    settings,
+                    Value::checked_add,
+                    Value::wrapping_add,
+                    Value::saturating_add,
+                );
             }
             b'-' => {
-                let a = self.pop();
-                let b = self.pop();
-                self.stack.push(b - a);
+                return self.arithmetic(
+                    '-',
+                    settings,
+                    Value::checked_sub,
+                    Value::wrapping_sub,
+                    Value::saturating_sub,
+                );
             }
             b'*' => {
-                let a = self.pop();
-                let b = self.pop();
-                self.stack.push(b * a);
+                return self.arithmetic(
+                    '*',
+                    settings,
+                    Value::checked_mul,
+                    Value::wrapping_mul,
+                    Value::saturating_mul,
+                );
             }
             b'/' => {
                 let a = self.pop();
                 let b = self.pop();
                 if a == 0 {
-                    return StepStatus::Error("Division by zero");
+                    return StepStatus::Error(format!("Division by zero ({b} / 0)"));
+                }
+                let mut quotient = b / a;
+                // rounds towards negative infinity rather than zero
+                if settings.floored_division && b % a != 0 && (b < 0) != (a < 0) {
+                    quotient -= 1;
                 }
-                self.stack.push(b / a);
+                self.push(quotient);
             }
             b'%' => {
                 let a = self.pop();
                 let b = self.pop();
                 if a == 0 {
-                    return StepStatus::Error("Division by zero");
+                    return StepStatus::Error(format!("Modulo by zero ({b} % 0)"));
                 }
-                self.stack.push(b % a);
+                let mut remainder = b % a;
+                // takes the sign of the divisor rather than the dividend
+                if settings.floored_division && remainder != 0 && (remainder < 0) != (a < 0) {
+                    remainder += a;
+                }
+                self.push(remainder);
             }
             b'`' => {
                 let a = self.pop();
                 let b = self.pop();
-                self.stack.push(if b > a { 1 } else { 0 });
+                self.push(if b > a { 1 } else { 0 });
             }
             b'\\' => {
                 let a = self.pop();
                 let b = self.pop();
-                self.stack.push(a);
-                self.stack.push(b);
+                self.push(a);
+                self.push(b);
             }
 
             // one op operations
             b'!' => {
                 let a = self.pop();
-                self.stack.push(if a == 0 { 1 } else { 0 });
+                self.push(if a == 0 { 1 } else { 0 });
             }
             b':' => {
                 let a = self.pop();
-                self.stack.push(a);
-                self.stack.push(a);
+                self.push(a);
+                self.push(a);
             }
             b'$' => {
                 self.pop();
@@ -366,8 +588,91 @@ impl State {
                 return StepStatus::NormalNoStep;
             }
 
+            b'j' => {
+                // jump over n cells, going backwards if it's negative. The field
+                // wraps, so that's the same as going forwards the rest of the way round
+                let count = self.pop();
+                let period = match self.direction {
+                    Direction::East | Direction::West => self.map.max_size.0,
+                    Direction::North | Direction::South => self.map.max_size.1,
+                }
+                .saturating_add(1);
+                for _ in 0..count.rem_euclid(period) {
+                    self.step_position_inner();
+                }
+            }
+
+            // a simplified version of Funge-98's stack stack, with a single second stack
+            // to move values onto and back, and no storage offset
+            b'{' => {
+                // move the top n values onto the second stack, keeping their order.
+                // Asking for more than there are moves them all, as an underflow
+                let count = self.pop();
+                if count < 0 {
+                    return StepStatus::Error(format!("Invalid block size {count}"));
+                }
+                let count = usize::try_from(count).unwrap_or(usize::MAX);
+                let moved = count.min(self.stack.len());
+                self.stack_underflows = self.stack_underflows.saturating_add(count - moved);
+                let block = self.stack.split_off(self.stack.len() - moved);
+                self.second_stack.extend(block);
+            }
+
+            b'}' => {
+                // move the top n values of the second stack back, keeping their order,
+                // or as many as there are
+                let count = self.pop();
+                if count < 0 {
+                    return StepStatus::Error(format!("Invalid block size {count}"));
+                }
+                let count = usize::try_from(count).unwrap_or(usize::MAX);
+                let moved = count.min(self.second_stack.len());
+                let block = self.second_stack.split_off(self.second_stack.len() - moved);
+                for val in block {
+                    self.push(val);
+                }
+            }
+
+            b'k' => {
+                // iterate: runs the next op that isn't a space n times, without moving onto it
+                let count = self.pop();
+                if count > MAX_ITERATIONS {
+                    return StepStatus::Error(format!("Iterate count {count} is too big"));
+                }
+                let start = (self.position, self.direction);
+                self.step_position_inner();
+                while self.map.get(self.position) == b' ' as Value && self.position != start.0 {
+                    self.step_position_inner();
+                }
+                let target = self.position;
+                self.position = start.0;
+
+                let Ok(op) = u8::try_from(self.map.get(target)) else {
+                    return invalid_operation(
+                        format!("Invalid operation {}", self.map.get(target)),
+                        &mut self.direction,
+                        settings,
+                    );
+                };
+                // nested, the counts would multiply past MAX_ITERATIONS, and each
+                // level would recurse through do_op
+                if op == b'k' {
+                    return StepStatus::Error("k can't iterate another k".into());
+                }
+                for _ in 0..count.max(0) {
+                    let status = self.do_op(op, settings);
+                    if !matches!(status, StepStatus::Normal) {
+                        return status;
+                    }
+                }
+                // carry on past the op, unless it moved the IP itself
+                if (self.position, self.direction) == start {
+                    self.position = target;
+                }
+            }
+
             // dynamic direction changes
-            b'?' => self.direction = rand::thread_rng().r#gen(),
+            b'?' => self.direction = self.rng.r#gen(),
             b'_' => {
                 let status = self.pop();
                 if status == 0 {
@@ -391,6 +696,7 @@ impl State {
                 let y = self.pop();
                 let x = self.pop();
                 let value = self.pop();
+                self.put_count += 1;
 
                 if settings.put_history.0 {
                     if let Some(prev_time) = self.put_history.get(&(x, y)) {
@@ -402,14 +708,21 @@ impl State {
                     }
                 }
 
-                self.map.set((x, y), value);
+                // skip hashing the position when nothing needs it
+                if settings.changed_cells.0 {
+                    self.written.insert((x, y));
+                }
+                self.write((x, y), value);
+                self.watch_hit |=
+                    !self.watchpoints.is_empty() && self.watchpoints.contains(&(x, y));
             }
 
             // get
             b'g' => {
                 let y = self.pop();
                 let x = self.pop();
-                self.stack.push(self.map.get((x, y)));
+                self.get_count += 1;
+                self.push(self.map.get((x, y)));
 
                 if settings.get_history.0 {
                     if let Some(prev_time) = self.get_history.get(&(x, y)) {
@@ -430,7 +743,7 @@ impl State {
                     match itr.next() {
                         None => {
                             if settings.non_blocking_input {
-                                self.stack.push(-1);
+                                self.push(-1);
                                 return StepStatus::Normal;
                             } else {
                                 return StepStatus::Breakpoint;
@@ -441,30 +754,43 @@ impl State {
                             num += (val as u8 - b'0') as Value;
                         }
                         Some(' ') => {
-                            self.stack.push(num);
                             self.input_buffer = itr.as_str().into();
+                            self.push(num);
                             return StepStatus::Normal;
                         }
-                        Some(_) => {
-                            return StepStatus::Error("Invalid input for Error::InvalidNumber");
+                        Some(chr) => {
+                            return StepStatus::Error(format!(
+                                "Invalid number input, unexpected {chr:?}"
+                            ));
                         }
                     }
                 }
             }
 
             b'~' => {
+                if let Some(byte) = self.pending_bytes.pop() {
+                    self.push(byte as Value);
+                    return StepStatus::Normal;
+                }
                 let mut itr = self.input_buffer.chars();
                 match itr.next() {
                     None => {
                         if settings.non_blocking_input {
-                            self.stack.push(-1);
+                            self.push(-1);
                         } else {
                             return StepStatus::Breakpoint;
                         }
                     }
                     Some(chr) => {
-                        self.stack.push(chr as Value);
                         self.input_buffer = itr.as_str().into();
+                        if settings.input_bytes {
+                            let mut buf = [0; 4];
+                            let bytes = chr.encode_utf8(&mut buf).as_bytes();
+                            self.pending_bytes.extend(bytes[1..].iter().rev());
+                            self.push(bytes[0] as Value);
+                        } else {
+                            self.push(chr as Value);
+                        }
                     }
                 }
             }
@@ -476,13 +802,23 @@ impl State {
             b'.' => {
                 let a = self.pop().to_string();
                 self.output.push_str(&a);
-                self.output.push(' ');
+                if settings.dot_trailing_space {
+                    self.output.push(' ');
+                }
+                self.output_truncated |= limit_output(&mut self.output, settings);
             }
             b',' => {
-                let Ok(a) = (self.pop() as u32).try_into() else {
-                    return StepStatus::Error("Invalid UTF-8 char");
-                };
-                self.output.push(a);
+                let a = self.pop();
+                if settings.input_bytes {
+                    // only the low byte, like a byte oriented interpreter
+                    push_output_byte(&mut self.output, &mut self.pending_output, a as u8);
+                } else {
+                    let Ok(chr) = (a as u32).try_into() else {
+                        return StepStatus::Error(format!("Invalid character {a}"));
+                    };
+                    self.output.push(chr);
+                }
+                self.output_truncated |= limit_output(&mut self.output, settings);
             }
 
             // befunge with graphics
@@ -493,35 +829,50 @@ impl State {
 
                 if y <= 0 || x <= 0 || x > Graphics::MAX_IMAGE_SIZE || y > Graphics::MAX_IMAGE_SIZE
                 {
-                    return StepStatus::Error("Out of bounds graphical operation");
+                    return StepStatus::Error(format!("Invalid canvas size {x}x{y}"));
                 }
 
                 self.graphics = Some(Graphics::new(x as usize, y as usize));
             }
 
+            b'S' => {
+                // canvas size, 0 0 if there isn't one
+                let (width, height) = match &self.graphics {
+                    Some(graphics) => (graphics.size.0 as Value, graphics.size.1 as Value),
+                    None => (0, 0),
+                };
+                self.push(width);
+                self.push(height);
+            }
+
+            // without a canvas these are no-ops, unless strict graphics is on
+            b'f' | b'x' | b'c' | b'C' | b'l' | b'L' | b'T' | b'z' if self.graphics.is_none() => {
+                if settings.strict_graphics {
+                    return StepStatus::Error(format!(
+                        "Graphics op {:?} before a canvas was set up with 's'",
+                        op as char
+                    ));
+                }
+            }
+
             b'f' => {
                 // configure color
+                let r = self.pop();
+                let g = self.pop();
+                let b = self.pop();
+                let (Ok(r), Ok(g), Ok(b)) = (r.try_into(), g.try_into(), b.try_into()) else {
+                    return StepStatus::Error(format!("Invalid color ({r}, {g}, {b})"));
+                };
                 if let Some(graphics) = &mut self.graphics {
-                    let r = self.stack.pop().unwrap_or(0).try_into();
-                    let g = self.stack.pop().unwrap_or(0).try_into();
-                    let b = self.stack.pop().unwrap_or(0).try_into();
-                    if let Ok(r) = r
-                        && let Ok(g) = g
-                        && let Ok(b) = b
-                    {
-                        graphics.current_color = Color32::from_rgb(r, g, b);
-                    } else {
-                        return StepStatus::Error("Out of bounds graphical operation");
-                    }
+                    graphics.current_color = Color32::from_rgb(r, g, b);
                 }
             }
 
             b'x' => {
                 // set pixel
+                let y = self.pop();
+                let x = self.pop();
                 if let Some(graphics) = &mut self.graphics {
-                    let y = self.stack.pop().unwrap_or(0);
-                    let x = self.stack.pop().unwrap_or(0);
-
                     return graphics.pixel(x, y);
                 }
             }
@@ -533,26 +884,48 @@ impl State {
                 }
             }
 
+            b'C' => {
+                // clear
+                if let Some(graphics) = &mut self.graphics {
+                    graphics.clear();
+                }
+            }
+
             b'u' => return StepStatus::SyncFrame, // update
 
-            b'l' => {
-                // line
-                if let Some(graphics) = &mut self.graphics {
-                    let y1: i32 = self.stack.pop().unwrap_or(0).try_into().unwrap();
-                    let x1: i32 = self.stack.pop().unwrap_or(0).try_into().unwrap();
-
-                    let y2: i32 = self.stack.pop().unwrap_or(0).try_into().unwrap();
-                    let x2: i32 = self.stack.pop().unwrap_or(0).try_into().unwrap();
-
-                    if x1 >= graphics.size.0 as i32
-                        || y1 >= graphics.size.1 as i32
-                        || x2 >= graphics.size.0 as i32
-                        || y2 >= graphics.size.1 as i32
-                    {
-                        return StepStatus::Error("Out of bounds graphical operation");
+            b'l' | b'L' => {
+                // line, clipped to the canvas. Thick lines take a width first
+                let width = if op == b'L' {
+                    let width = self.pop();
+                    if !(1..=Graphics::MAX_LINE_WIDTH).contains(&width) {
+                        return StepStatus::Error(format!("Invalid line width {width}"));
                     }
+                    width
+                } else {
+                    1
+                };
 
-                    graphics.line(x1, y1, x2, y2);
+                let y1 = self.pop();
+                let x1 = self.pop();
+
+                let y2 = self.pop();
+                let x2 = self.pop();
+                if let Some(graphics) = &mut self.graphics {
+                    graphics.line((x1, y1), (x2, y2), width);
+                }
+            }
+
+            b'T' => {
+                // text, popping y x and a length, then that many characters
+                let y = self.pop();
+                let x = self.pop();
+                let length = self.pop();
+                if !(0..=Graphics::MAX_TEXT_LENGTH).contains(&length) {
+                    return StepStatus::Error(format!("Invalid text length {length}"));
+                }
+                let text = (0..length).map(|_| self.pop()).collect::<Vec<_>>();
+                if let Some(graphics) = &mut self.graphics {
+                    graphics.text((x, y), &text);
                 }
             }
 
@@ -562,13 +935,17 @@ impl State {
                     if let Some(event) = graphics.event_queue.pop_front() {
                         match event {
                             //None is event 0
-                            GraphicalEvent::Close => self.stack.extend([1]),
+                            GraphicalEvent::Close => self.push(1),
                             //Event::KeyDown(key) => self.stack.extend([key,2]),
                             //Event::KeyUp(key) => self.stack.extend([key,3]),
-                            GraphicalEvent::MouseClick((x, y)) => self.stack.extend([x, y, 4]),
+                            GraphicalEvent::MouseClick((x, y)) => {
+                                self.push(x);
+                                self.push(y);
+                                self.push(4);
+                            }
                         }
                     } else {
-                        self.stack.push(0);
+                        self.push(0);
                     }
                 }
             }
@@ -576,7 +953,21 @@ impl State {
             // noop
             b' ' => (),
 
-            _ => return StepStatus::Error("Invalid operation"),
+            // not ascii, so shown as a number like values that don't fit in a byte
+            _ if !op.is_ascii() => {
+                return invalid_operation(
+                    format!("Invalid operation {op}"),
+                    &mut self.direction,
+                    settings,
+                );
+            }
+            _ => {
+                return invalid_operation(
+                    format!("Invalid operation {:?}", op as char),
+                    &mut self.direction,
+                    settings,
+                );
+            }
         };
         StepStatus::Normal
     }
@@ -587,7 +978,8 @@ impl Befunge for State {
         self.map.get(pos)
     }
     fn set(&mut self, pos: Position, val: Value) {
-        self.map.set(pos, val);
+        self.written.insert(pos);
+        self.write(pos, val);
     }
     fn step(&mut self, settings: &Settings) -> StepStatus {
         self.step(settings)
@@ -612,9 +1004,38 @@ impl Befunge for State {
     fn stack(&self) -> Vec<Value> {
         self.stack.clone()
     }
+    fn set_start(&mut self, stack: &[Value], direction: Direction, position: Position) {
+        self.stack = stack.to_vec();
+        self.direction = direction;
+        self.position = (position.0.max(0), position.1.max(0));
+    }
+    fn last_op(&self) -> Option<(Position, Instant)> {
+        self.last_op
+    }
+    fn stack_underflows(&self) -> usize {
+        self.stack_underflows
+    }
+    fn put_get_counts(&self) -> (usize, usize) {
+        (self.put_count, self.get_count)
+    }
+    fn second_stack(&self) -> Vec<Value> {
+        self.second_stack.clone()
+    }
+    fn op_counts(&self) -> &[u64; 128] {
+        &self.op_counts
+    }
+    fn stack_len(&self) -> usize {
+        self.stack.len()
+    }
+    fn stack_peek(&self, depth: usize) -> Option<Value> {
+        self.stack.iter().rev().nth(depth).copied()
+    }
     fn stdout(&self) -> &str {
         &self.output
     }
+    fn stdout_truncated(&self) -> bool {
+        self.output_truncated
+    }
     fn stdin(&mut self) -> &mut String {
         &mut self.input_buffer
     }
@@ -634,8 +1055,500 @@ impl Befunge for State {
     fn breakpoints(&mut self) -> &mut HashSet<Position> {
         &mut self.breakpoints
     }
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+    fn watchpoints(&mut self) -> &mut HashSet<Position> {
+        &mut self.watchpoints
+    }
+    fn written_cells(&self) -> &HashSet<Position> {
+        &self.written
+    }
+    fn cells_in_rect(&self, min: Position, max: Position) -> Vec<(Position, Value)> {
+        self.map.cells_in_rect(min, max)
+    }
+    fn cells(&self) -> Vec<(Position, Value)> {
+        let mut cells = self.map.iter_cells().collect::<Vec<_>>();
+        cells.sort_unstable_by_key(|((x, y), _)| (*y, *x));
+        cells
+    }
+    fn trace(&mut self) -> &mut Vec<TraceEntry> {
+        &mut self.trace
+    }
 
     fn serialize(&self) -> String {
         self.map.serialize()
     }
+    fn snapshot(&mut self) -> StateSnapshot {
+        let journal_len = self.journal_start + self.journal.get_or_insert_with(Vec::new).len();
+        StateSnapshot {
+            instruction_count: self.instruction_count,
+            position: self.position,
+            direction: self.direction,
+            string_mode: self.string_mode,
+            stack: self.stack.clone(),
+            second_stack: self.second_stack.clone(),
+            output: self.output.clone(),
+            input: self.input_buffer.clone(),
+            pending_bytes: self.pending_bytes.clone(),
+            journal_len,
+        }
+    }
+    fn restore(&mut self, snapshot: &StateSnapshot) {
+        if let Some(journal) = &mut self.journal {
+            let len = snapshot
+                .journal_len
+                .saturating_sub(self.journal_start)
+                .min(journal.len());
+            for (pos, val) in journal.drain(len..).rev() {
+                self.map.set(pos, val);
+            }
+        }
+        self.instruction_count = snapshot.instruction_count;
+        self.position = snapshot.position;
+        self.direction = snapshot.direction;
+        self.string_mode = snapshot.string_mode;
+        self.stack.clone_from(&snapshot.stack);
+        self.second_stack.clone_from(&snapshot.second_stack);
+        self.output.clone_from(&snapshot.output);
+        self.input_buffer.clone_from(&snapshot.input);
+        self.pending_bytes.clone_from(&snapshot.pending_bytes);
+    }
+    fn forget_snapshots_before(&mut self, oldest: Option<&StateSnapshot>) {
+        match (oldest, &mut self.journal) {
+            (Some(oldest), Some(journal)) => {
+                let len = oldest
+                    .journal_len
+                    .saturating_sub(self.journal_start)
+                    .min(journal.len());
+                journal.drain(..len);
+                self.journal_start += len;
+            }
+            (Some(_), None) => (),
+            (None, _) => {
+                self.journal = None;
+                self.journal_start = 0;
+            }
+        }
+    }
+    fn journal_len(&self) -> usize {
+        self.journal.as_ref().map_or(0, Vec::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::InvalidOperationBehaviour;
+    use crate::befunge::MAX_SEED;
+
+    /// Default settings, without the history tracking that only matters for drawing
+    fn settings() -> Settings {
+        let mut settings = Settings::default();
+        settings.pos_history.0 = false;
+        settings.put_history.0 = false;
+        settings.get_history.0 = false;
+        settings
+    }
+
+    fn state(program: &str) -> State {
+        State::new_from_fungespace(app::FungeSpace::new_from_string(program, None))
+    }
+
+    /// Steps until `@`, a breakpoint or an error, returning the state and what stopped it
+    fn run(program: &str, settings: &Settings) -> (State, StepStatus) {
+        finish(state(program), settings)
+    }
+
+    fn finish(mut state: State, settings: &Settings) -> (State, StepStatus) {
+        for _ in 0..10_000 {
+            match state.step(settings) {
+                StepStatus::Normal | StepStatus::NormalNoStep | StepStatus::SyncFrame => (),
+                status => return (state, status),
+            }
+        }
+        panic!("{:?} didn't stop", state.map.serialize());
+    }
+
+    #[test]
+    fn dot_trailing_space() {
+        let mut settings = settings();
+        settings.dot_trailing_space = true;
+        assert_eq!(run("1.@", &settings).0.output, "1 ");
+        settings.dot_trailing_space = false;
+        assert_eq!(run("1.@", &settings).0.output, "1");
+        assert_eq!(run("12..@", &settings).0.output, "21");
+    }
+
+    #[test]
+    fn strict_stack_underflow() {
+        let mut settings = settings();
+        let (state, status) = run("+@", &settings);
+        assert!(matches!(status, StepStatus::Breakpoint));
+        assert_eq!(state.stack_underflows, 2);
+        settings.strict_stack = true;
+        let (state, status) = run("+@", &settings);
+        assert!(matches!(status, StepStatus::Error(_)));
+        assert_eq!(state.position, (0, 0));
+    }
+
+    /// Everything a snapshot should bring back, including the field
+    fn observable(state: &State) -> impl PartialEq + std::fmt::Debug + use<> {
+        (
+            state.instruction_count,
+            state.position,
+            state.direction,
+            state.string_mode,
+            state.stack.clone(),
+            state.second_stack.clone(),
+            state.output.clone(),
+            state.map.serialize(),
+        )
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let settings = settings();
+        // writes over itself, prints and moves things to the second stack
+        let mut state = state("\"ab\"9:.50p1{1}65*60p>:#,_@");
+        for _ in 0..3 {
+            state.step(&settings);
+        }
+        let before = observable(&state);
+        let snapshot = state.snapshot();
+        for _ in 0..20 {
+            state.step(&settings);
+        }
+        assert_ne!(observable(&state), before);
+        state.restore(&snapshot);
+        assert_eq!(observable(&state), before);
+    }
+
+    #[test]
+    fn forgotten_snapshots_free_the_journal() {
+        let settings = settings();
+        let mut state = state("> 1+:02p v\n^        <");
+        let first = state.snapshot();
+        for _ in 0..40 {
+            state.step(&settings);
+        }
+        let second = state.snapshot();
+        let at_second = observable(&state);
+        for _ in 0..40 {
+            state.step(&settings);
+        }
+        let written = state.journal_len();
+        assert!(written > 0);
+
+        // only the writes since the second snapshot are still needed
+        state.forget_snapshots_before(Some(&second));
+        assert!(state.journal_len() < written);
+        state.restore(&second);
+        assert_eq!(observable(&state), at_second);
+        drop(first);
+
+        // and with none left, nothing is journaled
+        state.forget_snapshots_before(None);
+        for _ in 0..40 {
+            state.step(&settings);
+        }
+        assert_eq!(state.journal_len(), 0);
+    }
+
+    #[test]
+    fn watchpoint_breaks_on_put() {
+        let settings = settings();
+        // puts 7 at (5, 0), then would carry on to the end
+        let mut watched = state("750p   @");
+        watched.watchpoints.insert((5, 0));
+        let mut status = StepStatus::Normal;
+        for _ in 0..4 {
+            status = watched.step(&settings);
+        }
+        assert!(matches!(status, StepStatus::Breakpoint));
+        assert_eq!(watched.map.get((5, 0)), 7);
+        assert_eq!(watched.position, (4, 0));
+
+        // writing somewhere else doesn't stop it
+        let mut unwatched = state("750p   @");
+        unwatched.watchpoints.insert((6, 0));
+        for _ in 0..4 {
+            assert!(matches!(unwatched.step(&settings), StepStatus::Normal));
+        }
+    }
+
+    #[test]
+    fn strict_graphics_needs_a_canvas() {
+        let mut settings = settings();
+        let (state, status) = run("11x@", &settings);
+        assert!(matches!(status, StepStatus::Breakpoint));
+        assert!(state.graphics.is_none());
+        settings.strict_graphics = true;
+        let (state, status) = run("11x@", &settings);
+        assert!(matches!(status, StepStatus::Error(_)));
+        assert_eq!(state.position, (2, 0));
+    }
+
+    #[test]
+    fn canvas_size() {
+        let (state, _) = run("S34sS@", &settings());
+        assert_eq!(state.stack, [0, 0, 3, 4]);
+    }
+
+    #[test]
+    fn clear_canvas() {
+        let (state, _) = run("22s119fc@", &settings());
+        let graphics = state.graphics.unwrap();
+        assert!(
+            graphics
+                .texture
+                .iter()
+                .all(|&c| c == Color32::from_rgb(9, 1, 1))
+        );
+        let (state, _) = run("22s119fcC@", &settings());
+        let graphics = state.graphics.unwrap();
+        assert!(graphics.texture.iter().all(|&c| c == Color32::BLACK));
+        assert_eq!(graphics.current_color, Color32::from_rgb(9, 1, 1));
+    }
+
+    #[test]
+    fn line_off_the_canvas_is_clipped() {
+        let (state, status) = run("45*:s999f19+:05-:l@", &settings());
+        assert!(matches!(status, StepStatus::Breakpoint));
+        let graphics = state.graphics.unwrap();
+        for (i, &color) in graphics.texture.iter().enumerate() {
+            let (x, y) = (i % 20, i / 20);
+            // whether the far end is drawn is up to the line iterator
+            if (x, y) != (10, 10) {
+                assert_eq!(
+                    color != Color32::BLACK,
+                    x == y && x < 10,
+                    "pixel ({x}, {y})"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn floored_division() {
+        let program = "07-2/07-2%702-/702-%@";
+        let mut settings = settings();
+        let (state, _) = run(program, &settings);
+        assert_eq!(state.stack, [-3, -1, -3, 1]);
+        settings.floored_division = true;
+        let (state, _) = run(program, &settings);
+        assert_eq!(state.stack, [-4, 1, -4, -1]);
+    }
+
+    #[test]
+    fn stack_limit() {
+        let mut settings = settings();
+        settings.stack_limit = (true, 100);
+        let (_, status) = run("0>:1+", &settings);
+        assert!(matches!(status, StepStatus::Error(error) if error.starts_with("Stack overflow")));
+    }
+
+    #[test]
+    fn push_stops_at_the_stack_limit() {
+        let mut settings = settings();
+        settings.stack_limit = (true, 3);
+        let (state, status) = run("123@", &settings);
+        assert!(matches!(status, StepStatus::Breakpoint));
+        assert_eq!(state.stack, [1, 2, 3]);
+        for program in ["1234@", "\"abcd\"@", "3k:@"] {
+            let (state, status) = run(program, &settings);
+            assert!(matches!(status, StepStatus::Error(_)), "{program}");
+            assert_eq!(state.stack.len(), 3, "{program}");
+        }
+    }
+
+    /// Runs `program` with `i64::MAX` already on the stack
+    fn run_near_max(program: &str, overflow: Overflow) -> (State, StepStatus) {
+        let mut settings = settings();
+        settings.overflow = overflow;
+        let mut state = state(program);
+        state.stack.push(Value::MAX);
+        finish(state, &settings)
+    }
+
+    #[test]
+    fn overflow_wraps() {
+        let (state, _) = run_near_max(":1+\\2*@", Overflow::Wrap);
+        assert_eq!(state.stack, [Value::MIN, -2]);
+    }
+
+    #[test]
+    fn overflow_saturates() {
+        let (state, _) = run_near_max(":1+\\2*:0\\-1-1-@", Overflow::Saturate);
+        assert_eq!(state.stack, [Value::MAX, Value::MAX, Value::MIN]);
+    }
+
+    #[test]
+    fn overflow_errors() {
+        let (state, status) = run_near_max("1-1+@", Overflow::Error);
+        assert!(matches!(status, StepStatus::Breakpoint));
+        assert_eq!(state.stack, [Value::MAX]);
+        let (state, status) = run_near_max("1+@", Overflow::Error);
+        assert!(matches!(status, StepStatus::Error(error) if error.starts_with("Overflow")));
+        assert_eq!(state.position, (1, 0));
+    }
+
+    #[test]
+    fn pause_in_string_mode() {
+        let settings = settings();
+        let mut paused = state("\"ab\"@");
+        assert!(!paused.string_mode());
+        paused.step(&settings);
+        paused.step(&settings);
+        assert!(paused.string_mode());
+        assert_eq!(paused.stack, [b'a' as Value]);
+        let (state, _) = finish(paused, &settings);
+        assert!(!state.string_mode());
+        assert_eq!(state.stack, [b'a' as Value, b'b' as Value]);
+    }
+
+    #[test]
+    fn read_utf8_bytes() {
+        let mut settings = settings();
+        let mut reader = state("~~@");
+        reader.input_buffer = "é".into();
+        let (state, _) = finish(reader.clone(), &settings);
+        assert_eq!(state.stack, [0xe9]);
+        settings.input_bytes = true;
+        let (state, status) = finish(reader, &settings);
+        assert!(matches!(status, StepStatus::Breakpoint));
+        assert_eq!(state.position, (2, 0));
+        assert_eq!(state.stack, [0xc3, 0xa9]);
+    }
+
+    #[test]
+    fn write_utf8_bytes() {
+        let mut settings = settings();
+        settings.input_bytes = true;
+        let mut echo = state("~,~,~,~,~,@");
+        echo.input_buffer = "é€".into();
+        echo.step(&settings);
+        echo.step(&settings);
+        assert_eq!(echo.output, "");
+        let (state, _) = finish(echo, &settings);
+        assert_eq!(state.output, "é€");
+    }
+
+    #[test]
+    fn invalid_operation_by_number() {
+        let mut settings = settings();
+        settings.invalid_operation_behaviour = InvalidOperationBehaviour::Halt;
+        let mut invalid = state(" @");
+        invalid.map.set((0, 0), 200);
+        let (_, status) = finish(invalid, &settings);
+        assert!(matches!(status, StepStatus::Error(error) if error == "Invalid operation 200"));
+    }
+
+    #[test]
+    fn invalid_operation_behaviour() {
+        let mut settings = settings();
+        assert!(settings.invalid_operation_behaviour == InvalidOperationBehaviour::Ignore);
+        let (state, _) = run("1Q2@", &settings);
+        assert_eq!(state.stack, [1, 2]);
+        settings.invalid_operation_behaviour = InvalidOperationBehaviour::Reflect;
+        let (state, _) = run("1Q2@", &settings);
+        assert_eq!(state.direction, Direction::West);
+        assert_eq!(state.stack, [1, 1]);
+    }
+
+    #[test]
+    fn bridge_over_the_edge() {
+        let (m, n) = state("").map.max_size;
+        // each `#` jumps over the `1` on the far side, then runs `2@`
+        for (direction, cells) in [
+            (Direction::East, [(m, 0), (0, 0), (1, 0), (2, 0)]),
+            (Direction::West, [(0, 0), (m, 0), (m - 1, 0), (m - 2, 0)]),
+            (Direction::South, [(0, n), (0, 0), (0, 1), (0, 2)]),
+            (Direction::North, [(0, 0), (0, n), (0, n - 1), (0, n - 2)]),
+        ] {
+            let mut bridge = state("");
+            for (pos, op) in cells.into_iter().zip(*b"#12@") {
+                bridge.map.set(pos, op as Value);
+            }
+            bridge.position = cells[0];
+            bridge.direction = direction;
+            let (state, _) = finish(bridge, &settings());
+            assert_eq!(state.stack, [2], "{direction:?}");
+        }
+    }
+
+    #[test]
+    fn iterate() {
+        let (state, _) = run("123455k.@", &settings());
+        assert_eq!(state.output, "5 4 3 2 1 ");
+        assert_eq!(state.stack, []);
+        // 0 skips the op, and spaces before it are passed over
+        let (state, _) = run("10k.@", &settings());
+        assert_eq!(state.output, "");
+        assert_eq!(state.stack, [1]);
+        let (state, _) = run("52k  :@", &settings());
+        assert_eq!(state.stack, [5, 5, 5]);
+    }
+
+    #[test]
+    fn jump() {
+        let settings = settings();
+        let (skipped, _) = run("3j7892@", &settings);
+        assert_eq!(skipped.stack, [2]);
+        // lands two cells back from where it would have gone, on the `-`
+        let mut jumper = state("02-j");
+        for _ in 0..4 {
+            jumper.step(&settings);
+        }
+        assert_eq!(jumper.position, (2, 0));
+        assert_eq!(jumper.direction, Direction::East);
+    }
+
+    #[test]
+    fn second_stack() {
+        let (state, _) = run("12343{@", &settings());
+        assert_eq!(state.stack, [1]);
+        assert_eq!(state.second_stack, [2, 3, 4]);
+        let (state, _) = run("12343{53}@", &settings());
+        assert_eq!(state.stack, [1, 5, 2, 3, 4]);
+        assert_eq!(state.second_stack, []);
+    }
+
+    #[test]
+    fn seeds_survive_f64() {
+        for _ in 0..100 {
+            let seed = State::default().seed;
+            assert!(seed <= MAX_SEED);
+            assert_eq!(seed as f64 as u64, seed);
+        }
+        assert_eq!(MAX_SEED as f64 as u64, MAX_SEED);
+    }
+
+    #[test]
+    fn iterate_refuses_to_nest() {
+        for program in ["99kk.@", "99k  k.@", "0kk@"] {
+            let (state, status) = run(program, &settings());
+            assert!(matches!(status, StepStatus::Error(_)), "{program}");
+            assert_eq!(state.output, "", "{program}");
+        }
+    }
+
+    #[test]
+    fn second_stack_huge_count() {
+        let mut settings = settings();
+        let (state, status) = run("1299*:*:*:*{@", &settings);
+        assert!(matches!(status, StepStatus::Breakpoint));
+        assert_eq!(state.stack, []);
+        assert_eq!(state.second_stack, [1, 2]);
+        let (state, _) = run("122{99*:*:*:*}@", &settings);
+        assert_eq!(state.stack, [1, 2]);
+        assert_eq!(state.second_stack, []);
+        settings.strict_stack = true;
+        let (_, status) = run("1299*:*:*:*{@", &settings);
+        assert!(matches!(status, StepStatus::Error(_)));
+    }
 }
@@ -3,16 +3,17 @@ use egui::{
     Color32,
     ahash::{HashSet, HashSetExt},
 };
-use rand::Rng;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use std::iter;
 
 use egui::ahash::HashMap;
 
 use crate::{
-    app::{self, Settings},
+    app::{self, Overflow, Settings},
     befunge::{
-        Befunge, Direction, GraphicalEvent, Graphics, Position, StepStatus, Value, Visited,
-        WhereVisited,
+        Befunge, Direction, GraphicalEvent, Graphics, Position, StateSnapshot, StepStatus,
+        TraceEntry, Value, Visited, WhereVisited, invalid_operation, limit_output,
+        push_output_byte, random_seed, stack_overflow,
     },
 };
 
@@ -32,11 +33,40 @@ pub struct State {
     pub get_history: HashMap<Position, Instant>,
     pub put_history: HashMap<Position, Instant>,
     pub stack: Vec<i8>,
+    /// Values moved off the stack by `{`, for `}` to bring back
+    pub second_stack: Vec<i8>,
+    pub stack_underflows: usize,
+    /// Deepest the stack can get this step, from the stack limit setting
+    stack_limit: usize,
+    /// Whether a push this step was dropped for going past `stack_limit`
+    stack_overflowed: bool,
+    /// How many `p` and `g` ops have run
+    pub put_count: usize,
+    pub op_counts: [u64; 128],
+    pub get_count: usize,
     pub output: String,
+    pub output_truncated: bool,
     pub graphics: Option<Graphics>,
     pub breakpoints: HashSet<Position>,
+    pub watchpoints: HashSet<Position>,
+    watch_hit: bool,
+    /// Every cell that has been written to this run, while changed cells are shown
+    pub written: HashSet<Position>,
+    pub trace: Vec<TraceEntry>,
+    pub last_op: Option<(Position, Instant)>,
+    /// Seed `rng` was last seeded with, so a run can be reproduced
+    pub seed: u64,
+    rng: StdRng,
+    /// Previous values of written cells, once a snapshot has been taken
+    journal: Option<Vec<((i8, i8), i8)>>,
+    /// Entries dropped from the front of the journal, so snapshot lengths still line up
+    journal_start: usize,
     //pub input_buffer: VecDeque<i64>,
     pub input_buffer: String,
+    /// Bytes left over from a character read as UTF-8 bytes, last byte first
+    pending_bytes: Vec<u8>,
+    /// Bytes output by `,` that don't make up a whole character yet
+    pending_output: Vec<u8>,
 }
 
 // TODO: implement FungeSpaceTrait
@@ -76,15 +106,20 @@ impl FungeSpace {
         self.zero_page[(pos.0 as usize) + (pos.1 as usize) * 128]
     }
 
+    /// Cells that aren't spaces, like the other fungespaces give
     pub fn entries(&self) -> impl Iterator<Item = (Position, i8)> {
-        self.zero_page.iter().enumerate().map(|(i, val)| {
-            let i = i as i64;
-            ((i % 127, i / 127), *val)
-        })
+        self.zero_page
+            .iter()
+            .enumerate()
+            .filter(|(_, val)| **val != b' ' as i8)
+            .map(|(i, val)| {
+                let i = i as i64;
+                ((i % 128, i / 128), *val)
+            })
     }
 
     pub fn serialize(&self) -> String {
-        let mut lines: Vec<Vec<char>> = vec![vec![]; 127];
+        let mut lines: Vec<Vec<char>> = vec![vec![]; 128];
         for ((x, y), val) in self.entries() {
             let line = &mut lines[y as usize];
             if line.len() <= x as usize {
@@ -98,15 +133,19 @@ impl FungeSpace {
         }
         let mut out = String::new();
         for line in lines {
-            out += &line.iter().collect::<String>();
+            out += line.iter().collect::<String>().trim_end_matches(' ');
             out += "\n";
         }
+        // drop trailing blank rows, but keep the final newline
+        out.truncate(out.trim_end_matches('\n').len());
+        out.push('\n');
         out
     }
 }
 
 impl Default for State {
     fn default() -> Self {
+        let seed = random_seed();
         Self {
             instruction_count: 0,
             map: FungeSpace::new(),
@@ -117,18 +156,81 @@ impl Default for State {
             put_history: HashMap::default(),
             get_history: HashMap::default(),
             stack: Vec::new(),
+            second_stack: Vec::new(),
+            stack_underflows: 0,
+            stack_limit: usize::MAX,
+            stack_overflowed: false,
+            put_count: 0,
+            op_counts: [0; 128],
+            get_count: 0,
             output: String::new(),
+            output_truncated: false,
             graphics: None,
             breakpoints: HashSet::new(),
+            watchpoints: HashSet::new(),
+            watch_hit: false,
+            written: HashSet::new(),
+            trace: Vec::new(),
+            last_op: None,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            journal: None,
+            journal_start: 0,
             //input_buffer: VecDeque::new(),
             input_buffer: String::new(),
+            pending_bytes: Vec::new(),
+            pending_output: Vec::new(),
         }
     }
 }
 
 impl State {
+    /// Writes to the field, journaling the old value if snapshots are in use
+    fn write(&mut self, pos: (i8, i8), val: i8) {
+        if let Some(journal) = &mut self.journal {
+            journal.push((pos, self.map.get(pos)));
+        }
+        self.map.set(pos, val);
+    }
+
+    /// All pushes go through here, so stack growth can be dealt with in one place.
+    /// Pushes past the stack limit are dropped, and the step halts with an error
+    fn push(&mut self, value: i8) {
+        if self.stack.len() >= self.stack_limit {
+            self.stack_overflowed = true;
+            return;
+        }
+        self.stack.push(value);
+    }
+
+    /// Pops `a` then `b` and pushes `b op a`, dealing with overflow how the settings say to
+    fn arithmetic(
+        &mut self,
+        op: char,
+        settings: &Settings,
+        checked: fn(i8, i8) -> Option<i8>,
+        wrapping: fn(i8, i8) -> i8,
+        saturating: fn(i8, i8) -> i8,
+    ) -> StepStatus {
+        let a = self.pop();
+        let b = self.pop();
+        let result = match settings.overflow {
+            Overflow::Wrap => wrapping(b, a),
+            Overflow::Saturate => saturating(b, a),
+            Overflow::Error => match checked(b, a) {
+                Some(result) => result,
+                None => return StepStatus::Error(format!("Overflow ({b} {op} {a})")),
+            },
+        };
+        self.push(result);
+        StepStatus::Normal
+    }
+
     fn pop(&mut self) -> i8 {
-        self.stack.pop().unwrap_or(0)
+        self.stack.pop().unwrap_or_else(|| {
+            self.stack_underflows += 1;
+            0
+        })
     }
 
     pub fn new_from_fungespace(fungespace: app::FungeSpace) -> Self {
@@ -194,10 +296,11 @@ impl State {
     fn step_position_inner(&mut self) {
         let (x, y) = self.position;
         match self.direction {
-            Direction::North => self.position = (x, y - 1),
-            Direction::South => self.position = (x, y + 1),
-            Direction::East => self.position = (x + 1, y),
-            Direction::West => self.position = (x - 1, y),
+            // 127 steps on to -128, which wraps round to 0 below
+            Direction::North => self.position = (x, y.wrapping_sub(1)),
+            Direction::South => self.position = (x, y.wrapping_add(1)),
+            Direction::East => self.position = (x.wrapping_add(1), y),
+            Direction::West => self.position = (x.wrapping_sub(1), y),
         }
 
         if self.position.0 == -1 {
@@ -214,8 +317,25 @@ impl State {
     }
 
     pub fn step(&mut self, settings: &Settings) -> StepStatus {
+        if settings.record_trace && self.trace.len() < TraceEntry::MAX_TRACE_LENGTH {
+            self.trace.push(TraceEntry {
+                step: self.instruction_count,
+                position: (self.position.0 as i64, self.position.1 as i64),
+                op: self.map.get(self.position) as Value,
+                stack_depth: self.stack.len(),
+            });
+        }
+        if settings.last_op_flash.0 {
+            self.last_op = Some((
+                (self.position.0 as i64, self.position.1 as i64),
+                Instant::recent(),
+            ));
+        }
         self.instruction_count += 1;
         let status = self.step_inner(settings);
+        if std::mem::take(&mut self.watch_hit) {
+            return StepStatus::Breakpoint;
+        }
         if self
             .breakpoints
             .contains(&(self.position.0 as i64, self.position.1 as i64))
@@ -239,17 +359,36 @@ impl State {
 
     fn step_inner(&mut self, settings: &Settings) -> StepStatus {
         let op = self.map.get(self.position);
+        self.stack_limit = if settings.stack_limit.0 {
+            settings.stack_limit.1
+        } else {
+            usize::MAX
+        };
+        self.stack_overflowed = false;
 
         if self.string_mode {
             if op == b'"' as i8 {
                 self.string_mode = false;
             } else {
-                self.stack.push(op);
+                self.push(op);
+                if std::mem::take(&mut self.stack_overflowed) {
+                    return stack_overflow(settings);
+                }
             }
             self.step_position(settings);
             StepStatus::Normal
-        } else if let Ok(op) = op.try_into() {
+        } else if let Ok(op) = u8::try_from(op) {
+            if settings.count_ops && op < 128 {
+                self.op_counts[op as usize] += 1;
+            }
+            let underflows = self.stack_underflows;
             let status = self.do_op(op, settings);
+            if settings.strict_stack && self.stack_underflows > underflows {
+                return StepStatus::Error(format!("Stack underflow on {:?}", op as char));
+            }
+            if std::mem::take(&mut self.stack_overflowed) {
+                return stack_overflow(settings);
+            }
             match status {
                 StepStatus::Normal | StepStatus::SyncFrame => {
                     self.step_position(settings);
@@ -258,7 +397,15 @@ impl State {
             };
             status
         } else {
-            StepStatus::Error("Invalid operation")
+            let status = invalid_operation(
+                format!("Invalid operation {op}"),
+                &mut self.direction,
+                settings,
+            );
+            if let StepStatus::Normal = status {
+                self.step_position(settings);
+            }
+            status
         }
     }
 
@@ -266,61 +413,83 @@ impl State {
         match op {
             b'"' => self.string_mode = true,
 
-            b'0'..=b'9' => self.stack.push((op - b'0') as i8),
+            b'0'..=b'9' => self.push((op - b'0') as i8),
 
             // 2 op operations
             b'+' => {
-                let a = self.pop();
-                let b = self.pop();
-                self.stack.push(b + a);
+                return self.arithmetic(
+                    '+',
+                    settings,
+                    i8::checked_add,
+                    i8::wrapping_add,
+                    i8::saturating_add,
+                );
             }
             b'-' => {
-                let a = self.pop();
-                let b = self.pop();
-                self.stack.push(b - a);
+                return self.arithmetic(
+                    '-',
+                    settings,
+                    i8::checked_sub,
+                    i8::wrapping_sub,
+                    i8::saturating_sub,
+                );
             }
             b'*' => {
-                let a = self.pop();
-                let b = self.pop();
-                self.stack.push(b * a);
+                return self.arithmetic(
+                    '*',
+                    settings,
+                    i8::checked_mul,
+                    i8::wrapping_mul,
+                    i8::saturating_mul,
+                );
             }
             b'/' => {
                 let a = self.pop();
                 let b = self.pop();
                 if a == 0 {
-                    return StepStatus::Error("Division by zero");
+                    return StepStatus::Error(format!("Division by zero ({b} / 0)"));
                 }
-                self.stack.push(b / a);
+                let mut quotient = b / a;
+                // rounds towards negative infinity rather than zero
+                if settings.floored_division && b % a != 0 && (b < 0) != (a < 0) {
+                    quotient -= 1;
+                }
+                self.push(quotient);
             }
             b'%' => {
                 let a = self.pop();
                 let b = self.pop();
                 if a == 0 {
-                    return StepStatus::Error("Division by zero");
+                    return StepStatus::Error(format!("Modulo by zero ({b} % 0)"));
+                }
+                let mut remainder = b % a;
+                // takes the sign of the divisor rather than the dividend
+                if settings.floored_division && remainder != 0 && (remainder < 0) != (a < 0) {
+                    remainder += a;
                 }
-                self.stack.push(b % a);
+                self.push(remainder);
             }
             b'`' => {
                 let a = self.pop();
                 let b = self.pop();
-                self.stack.push(if b > a { 1 } else { 0 });
+                self.push(if b > a { 1 } else { 0 });
             }
             b'\\' => {
                 let a = self.pop();
                 let b = self.pop();
-                self.stack.push(a);
-                self.stack.push(b);
+                self.push(a);
+                self.push(b);
             }
 
             // one op operations
             b'!' => {
                 let a = self.pop();
-                self.stack.push(if a == 0 { 1 } else { 0 });
+                self.push(if a == 0 { 1 } else { 0 });
             }
             b':' => {
                 let a = self.pop();
-                self.stack.push(a);
-                self.stack.push(a);
+                self.push(a);
+                self.push(a);
             }
             b'$' => {
                 self.pop();
@@ -337,8 +506,83 @@ impl State {
                 return StepStatus::NormalNoStep;
             }
 
+            b'j' => {
+                // jump over n cells, going backwards if it's negative. The field
+                // wraps, so that's the same as going forwards the rest of the way round
+                let count = self.pop();
+                for _ in 0..(count as i64).rem_euclid(128) {
+                    self.step_position_inner();
+                }
+            }
+
+            // a simplified version of Funge-98's stack stack, with a single second stack
+            // to move values onto and back, and no storage offset
+            b'{' => {
+                // move the top n values onto the second stack, keeping their order.
+                // Asking for more than there are moves them all, as an underflow
+                let count = self.pop();
+                if count < 0 {
+                    return StepStatus::Error(format!("Invalid block size {count}"));
+                }
+                let count = usize::try_from(count).unwrap_or(usize::MAX);
+                let moved = count.min(self.stack.len());
+                self.stack_underflows = self.stack_underflows.saturating_add(count - moved);
+                let block = self.stack.split_off(self.stack.len() - moved);
+                self.second_stack.extend(block);
+            }
+
+            b'}' => {
+                // move the top n values of the second stack back, keeping their order,
+                // or as many as there are
+                let count = self.pop();
+                if count < 0 {
+                    return StepStatus::Error(format!("Invalid block size {count}"));
+                }
+                let count = usize::try_from(count).unwrap_or(usize::MAX);
+                let moved = count.min(self.second_stack.len());
+                let block = self.second_stack.split_off(self.second_stack.len() - moved);
+                for val in block {
+                    self.push(val);
+                }
+            }
+
+            b'k' => {
+                // iterate: runs the next op that isn't a space n times, without moving onto it
+                let count = self.pop();
+                let start = (self.position, self.direction);
+                self.step_position_inner();
+                while self.map.get(self.position) == b' ' as i8 && self.position != start.0 {
+                    self.step_position_inner();
+                }
+                let target = self.position;
+                self.position = start.0;
+
+                let Ok(op) = u8::try_from(self.map.get(target)) else {
+                    return invalid_operation(
+                        format!("Invalid operation {}", self.map.get(target)),
+                        &mut self.direction,
+                        settings,
+                    );
+                };
+                // nested, the counts would multiply past MAX_ITERATIONS, and each
+                // level would recurse through do_op
+                if op == b'k' {
+                    return StepStatus::Error("k can't iterate another k".into());
+                }
+                for _ in 0..count.max(0) {
+                    let status = self.do_op(op, settings);
+                    if !matches!(status, StepStatus::Normal) {
+                        return status;
+                    }
+                }
+                // carry on past the op, unless it moved the IP itself
+                if (self.position, self.direction) == start {
+                    self.position = target;
+                }
+            }
+
             // dynamic direction changes
-            b'?' => self.direction = rand::thread_rng().r#gen(),
+            b'?' => self.direction = self.rng.r#gen(),
             b'_' => {
                 let status = self.pop();
                 if status == 0 {
@@ -362,6 +606,7 @@ impl State {
                 let y = self.pop();
                 let x = self.pop();
                 let value = self.pop();
+                self.put_count += 1;
 
                 if settings.put_history.0 {
                     if let Some(prev_time) = self.put_history.get(&(x as i64, y as i64)) {
@@ -375,14 +620,21 @@ impl State {
                     }
                 }
 
-                self.map.set((x, y), value);
+                // skip hashing the position when nothing needs it
+                if settings.changed_cells.0 {
+                    self.written.insert((x as i64, y as i64));
+                }
+                self.write((x, y), value);
+                self.watch_hit |= !self.watchpoints.is_empty()
+                    && self.watchpoints.contains(&(x as i64, y as i64));
             }
 
             // get
             b'g' => {
                 let y = self.pop();
                 let x = self.pop();
-                self.stack.push(self.map.get((x, y)));
+                self.get_count += 1;
+                self.push(self.map.get((x, y)));
 
                 if settings.get_history.0 {
                     if let Some(prev_time) = self.get_history.get(&(x as i64, y as i64)) {
@@ -405,7 +657,8 @@ impl State {
                     match itr.next() {
                         None => {
                             if settings.non_blocking_input {
-                                self.stack.push(-1);
+                                self.push(-1);
+                                return StepStatus::Normal;
                             } else {
                                 return StepStatus::Breakpoint;
                             }
@@ -415,30 +668,43 @@ impl State {
                             num += (val as u8 - b'0') as i8;
                         }
                         Some(' ') => {
-                            self.stack.push(num);
                             self.input_buffer = itr.as_str().into();
+                            self.push(num);
                             return StepStatus::Normal;
                         }
-                        Some(_) => {
-                            return StepStatus::Error("Invalid input for Error::InvalidNumber");
+                        Some(chr) => {
+                            return StepStatus::Error(format!(
+                                "Invalid number input, unexpected {chr:?}"
+                            ));
                         }
                     }
                 }
             }
 
             b'~' => {
+                if let Some(byte) = self.pending_bytes.pop() {
+                    self.push(byte as i8);
+                    return StepStatus::Normal;
+                }
                 let mut itr = self.input_buffer.chars();
                 match itr.next() {
                     None => {
                         if settings.non_blocking_input {
-                            self.stack.push(-1);
+                            self.push(-1);
                         } else {
                             return StepStatus::Breakpoint;
                         }
                     }
                     Some(chr) => {
-                        self.stack.push(chr as i8);
                         self.input_buffer = itr.as_str().into();
+                        if settings.input_bytes {
+                            let mut buf = [0; 4];
+                            let bytes = chr.encode_utf8(&mut buf).as_bytes();
+                            self.pending_bytes.extend(bytes[1..].iter().rev());
+                            self.push(bytes[0] as i8);
+                        } else {
+                            self.push(chr as i8);
+                        }
                     }
                 }
             }
@@ -450,13 +716,23 @@ impl State {
             b'.' => {
                 let a = self.pop().to_string();
                 self.output.push_str(&a);
-                self.output.push(' ');
+                if settings.dot_trailing_space {
+                    self.output.push(' ');
+                }
+                self.output_truncated |= limit_output(&mut self.output, settings);
             }
             b',' => {
-                let Ok(a) = (self.pop() as u32).try_into() else {
-                    return StepStatus::Error("Invalid UTF-8 char");
-                };
-                self.output.push(a);
+                let a = self.pop();
+                if settings.input_bytes {
+                    // only the low byte, like a byte oriented interpreter
+                    push_output_byte(&mut self.output, &mut self.pending_output, a as u8);
+                } else {
+                    let Ok(chr) = (a as u32).try_into() else {
+                        return StepStatus::Error(format!("Invalid character {a}"));
+                    };
+                    self.output.push(chr);
+                }
+                self.output_truncated |= limit_output(&mut self.output, settings);
             }
 
             // befunge with graphics
@@ -466,29 +742,47 @@ impl State {
                 let x = self.pop();
 
                 if y <= 0 || x <= 0 {
-                    return StepStatus::Error("Out of bounds graphical operation");
+                    return StepStatus::Error(format!("Invalid canvas size {x}x{y}"));
                 }
 
                 self.graphics = Some(Graphics::new(x as usize, y as usize));
             }
 
+            b'S' => {
+                // canvas size, 0 0 if there isn't one
+                let (width, height) = match &self.graphics {
+                    Some(graphics) => (graphics.size.0 as i8, graphics.size.1 as i8),
+                    None => (0, 0),
+                };
+                self.push(width);
+                self.push(height);
+            }
+
+            // without a canvas these are no-ops, unless strict graphics is on
+            b'f' | b'x' | b'c' | b'C' | b'l' | b'L' | b'T' | b'z' if self.graphics.is_none() => {
+                if settings.strict_graphics {
+                    return StepStatus::Error(format!(
+                        "Graphics op {:?} before a canvas was set up with 's'",
+                        op as char
+                    ));
+                }
+            }
+
             b'f' => {
                 // configure color
+                let r = self.pop() as u8;
+                let g = self.pop() as u8;
+                let b = self.pop() as u8;
                 if let Some(graphics) = &mut self.graphics {
-                    let r = self.stack.pop().unwrap_or(0) as u8;
-                    let g = self.stack.pop().unwrap_or(0) as u8;
-                    let b = self.stack.pop().unwrap_or(0) as u8;
-
                     graphics.current_color = Color32::from_rgb(r, g, b);
                 }
             }
 
             b'x' => {
                 // set pixel
+                let y = self.pop();
+                let x = self.pop();
                 if let Some(graphics) = &mut self.graphics {
-                    let y = self.stack.pop().unwrap_or(0);
-                    let x = self.stack.pop().unwrap_or(0);
-
                     return graphics.pixel(x as i64, y as i64);
                 }
             }
@@ -500,26 +794,48 @@ impl State {
                 }
             }
 
+            b'C' => {
+                // clear
+                if let Some(graphics) = &mut self.graphics {
+                    graphics.clear();
+                }
+            }
+
             b'u' => return StepStatus::SyncFrame, // update
 
-            b'l' => {
-                // line
-                if let Some(graphics) = &mut self.graphics {
-                    let y1 = self.stack.pop().unwrap_or(0) as i32;
-                    let x1 = self.stack.pop().unwrap_or(0) as i32;
-
-                    let y2 = self.stack.pop().unwrap_or(0) as i32;
-                    let x2 = self.stack.pop().unwrap_or(0) as i32;
-
-                    if x1 >= graphics.size.0 as i32
-                        || y1 >= graphics.size.1 as i32
-                        || x2 >= graphics.size.0 as i32
-                        || y2 >= graphics.size.1 as i32
-                    {
-                        return StepStatus::Error("Out of bounds graphical operation");
+            b'l' | b'L' => {
+                // line, clipped to the canvas. Thick lines take a width first
+                let width = if op == b'L' {
+                    let width = self.pop() as i64;
+                    if !(1..=Graphics::MAX_LINE_WIDTH).contains(&width) {
+                        return StepStatus::Error(format!("Invalid line width {width}"));
                     }
+                    width
+                } else {
+                    1
+                };
+
+                let y1 = self.pop() as i64;
+                let x1 = self.pop() as i64;
 
-                    graphics.line(x1, y1, x2, y2);
+                let y2 = self.pop() as i64;
+                let x2 = self.pop() as i64;
+                if let Some(graphics) = &mut self.graphics {
+                    graphics.line((x1, y1), (x2, y2), width);
+                }
+            }
+
+            b'T' => {
+                // text, popping y x and a length, then that many characters
+                let y = self.pop() as i64;
+                let x = self.pop() as i64;
+                let length = self.pop() as i64;
+                if !(0..=Graphics::MAX_TEXT_LENGTH).contains(&length) {
+                    return StepStatus::Error(format!("Invalid text length {length}"));
+                }
+                let text = (0..length).map(|_| self.pop() as i64).collect::<Vec<_>>();
+                if let Some(graphics) = &mut self.graphics {
+                    graphics.text((x, y), &text);
                 }
             }
 
@@ -529,15 +845,17 @@ impl State {
                     if let Some(event) = graphics.event_queue.pop_front() {
                         match event {
                             //None is event 0
-                            GraphicalEvent::Close => self.stack.extend([1]),
+                            GraphicalEvent::Close => self.push(1),
                             //Event::KeyDown(key) => self.stack.extend([key,2]),
                             //Event::KeyUp(key) => self.stack.extend([key,3]),
                             GraphicalEvent::MouseClick((x, y)) => {
-                                self.stack.extend([x as i8, y as i8, 4])
+                                self.push(x as i8);
+                                self.push(y as i8);
+                                self.push(4);
                             }
                         }
                     } else {
-                        self.stack.push(0);
+                        self.push(0);
                     }
                 }
             }
@@ -545,7 +863,21 @@ impl State {
             // noop
             b' ' => (),
 
-            _ => return StepStatus::Error("Invalid operation"),
+            // not ascii, so shown as a number like values that don't fit in a byte
+            _ if !op.is_ascii() => {
+                return invalid_operation(
+                    format!("Invalid operation {op}"),
+                    &mut self.direction,
+                    settings,
+                );
+            }
+            _ => {
+                return invalid_operation(
+                    format!("Invalid operation {:?}", op as char),
+                    &mut self.direction,
+                    settings,
+                );
+            }
         };
         StepStatus::Normal
     }
@@ -556,7 +888,8 @@ impl Befunge for State {
         self.map.get((pos.0 as i8, pos.1 as i8)) as Value
     }
     fn set(&mut self, pos: Position, val: Value) {
-        self.map.set((pos.0 as i8, pos.1 as i8), val as i8);
+        self.written.insert(pos);
+        self.write((pos.0 as i8, pos.1 as i8), val as i8);
     }
     fn step(&mut self, settings: &Settings) -> StepStatus {
         self.step(settings)
@@ -581,9 +914,41 @@ impl Befunge for State {
     fn stack(&self) -> Vec<i64> {
         self.stack.iter().map(|a| *a as i64).collect::<Vec<_>>()
     }
+    fn set_start(&mut self, stack: &[Value], direction: Direction, position: Position) {
+        self.stack = stack.iter().map(|a| *a as i8).collect();
+        self.direction = direction;
+        self.position = (
+            position.0.clamp(0, 127) as i8,
+            position.1.clamp(0, 127) as i8,
+        );
+    }
+    fn last_op(&self) -> Option<(Position, Instant)> {
+        self.last_op
+    }
+    fn stack_underflows(&self) -> usize {
+        self.stack_underflows
+    }
+    fn put_get_counts(&self) -> (usize, usize) {
+        (self.put_count, self.get_count)
+    }
+    fn second_stack(&self) -> Vec<Value> {
+        self.second_stack.iter().map(|a| *a as Value).collect()
+    }
+    fn op_counts(&self) -> &[u64; 128] {
+        &self.op_counts
+    }
+    fn stack_len(&self) -> usize {
+        self.stack.len()
+    }
+    fn stack_peek(&self, depth: usize) -> Option<Value> {
+        self.stack.iter().rev().nth(depth).map(|val| *val as Value)
+    }
     fn stdout(&self) -> &str {
         &self.output
     }
+    fn stdout_truncated(&self) -> bool {
+        self.output_truncated
+    }
     fn stdin(&mut self) -> &mut String {
         &mut self.input_buffer
     }
@@ -603,8 +968,113 @@ impl Befunge for State {
     fn breakpoints(&mut self) -> &mut HashSet<Position> {
         &mut self.breakpoints
     }
+    fn seed(&self) -> u64 {
+        self.seed
+    }
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+    fn watchpoints(&mut self) -> &mut HashSet<Position> {
+        &mut self.watchpoints
+    }
+    fn written_cells(&self) -> &HashSet<Position> {
+        &self.written
+    }
+    fn cells_in_rect(&self, min: Position, max: Position) -> Vec<(Position, Value)> {
+        // the whole space is at most 128x128, so just look everything up
+        (min.1..=max.1)
+            .flat_map(|y| (min.0..=max.0).map(move |x| (x, y)))
+            .map(|pos| (pos, self.get(pos)))
+            .filter(|(_, val)| *val != b' ' as Value)
+            .collect()
+    }
+    fn cells(&self) -> Vec<(Position, Value)> {
+        self.cells_in_rect((0, 0), (127, 127))
+    }
+    fn trace(&mut self) -> &mut Vec<TraceEntry> {
+        &mut self.trace
+    }
 
     fn serialize(&self) -> String {
         self.map.serialize()
     }
+    fn snapshot(&mut self) -> StateSnapshot {
+        let journal_len = self.journal_start + self.journal.get_or_insert_with(Vec::new).len();
+        StateSnapshot {
+            instruction_count: self.instruction_count,
+            position: (self.position.0 as i64, self.position.1 as i64),
+            direction: self.direction,
+            string_mode: self.string_mode,
+            stack: self.stack.iter().map(|val| *val as Value).collect(),
+            second_stack: self.second_stack.iter().map(|val| *val as Value).collect(),
+            output: self.output.clone(),
+            input: self.input_buffer.clone(),
+            pending_bytes: self.pending_bytes.clone(),
+            journal_len,
+        }
+    }
+    fn restore(&mut self, snapshot: &StateSnapshot) {
+        if let Some(journal) = &mut self.journal {
+            let len = snapshot
+                .journal_len
+                .saturating_sub(self.journal_start)
+                .min(journal.len());
+            for (pos, val) in journal.drain(len..).rev() {
+                self.map.set(pos, val);
+            }
+        }
+        self.instruction_count = snapshot.instruction_count;
+        self.position = (snapshot.position.0 as i8, snapshot.position.1 as i8);
+        self.direction = snapshot.direction;
+        self.string_mode = snapshot.string_mode;
+        self.stack = snapshot.stack.iter().map(|val| *val as i8).collect();
+        self.second_stack = snapshot.second_stack.iter().map(|val| *val as i8).collect();
+        self.output.clone_from(&snapshot.output);
+        self.input_buffer.clone_from(&snapshot.input);
+        self.pending_bytes.clone_from(&snapshot.pending_bytes);
+    }
+    fn forget_snapshots_before(&mut self, oldest: Option<&StateSnapshot>) {
+        match (oldest, &mut self.journal) {
+            (Some(oldest), Some(journal)) => {
+                let len = oldest
+                    .journal_len
+                    .saturating_sub(self.journal_start)
+                    .min(journal.len());
+                journal.drain(..len);
+                self.journal_start += len;
+            }
+            (Some(_), None) => (),
+            (None, _) => {
+                self.journal = None;
+                self.journal_start = 0;
+            }
+        }
+    }
+    fn journal_len(&self) -> usize {
+        self.journal.as_ref().map_or(0, Vec::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_bottom_row() {
+        let mut field = FungeSpace::new();
+        assert_eq!(field.serialize(), "\n");
+        field.set((3, 127), b'x' as i8);
+        field.set((127, 0), b'@' as i8);
+        let serialized = field.serialize();
+        let lines = serialized.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 128);
+        assert_eq!(lines[0], format!("{}@", " ".repeat(127)));
+        assert_eq!(lines[127], "   x");
+        assert!(lines[1..127].iter().all(|line| line.is_empty()));
+        // a space written over a cell clears it, like the other fields
+        field.set((127, 0), b' ' as i8);
+        field.set((3, 127), b' ' as i8);
+        assert_eq!(field.serialize(), "\n");
+    }
 }
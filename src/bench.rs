@@ -0,0 +1,80 @@
+//! Entry points for the benchmarks in `benches/`, which can only reach the public API
+
+use crate::{
+    app::{self, Settings},
+    befunge::{FungeSpaceTrait, Position, StepStatus},
+    befunge93,
+};
+
+/// The same cells stored the way the befunge93 interpreter does, in tiles,
+/// and the way the editor does, in a plain hashmap
+pub struct Field {
+    tiled: befunge93::FungeSpace,
+    hashmap: app::FungeSpace,
+}
+
+impl Field {
+    /// A `size` by `size` field with a cell every `spacing` positions along each row
+    pub fn scattered(size: i64, spacing: i64) -> Self {
+        let mut hashmap = app::FungeSpace::default();
+        for y in 0..size {
+            for x in (y % spacing..size).step_by(spacing as usize) {
+                hashmap.set((x, y), b'#' as i64);
+            }
+        }
+        Self {
+            tiled: befunge93::FungeSpace::new_from_fungespace(hashmap.clone()),
+            hashmap,
+        }
+    }
+
+    /// Number of cells between `min` and `max` in the tiled field
+    pub fn tiled_rect(&self, min: Position, max: Position) -> usize {
+        self.tiled.cells_in_rect(min, max).len()
+    }
+
+    /// Number of cells between `min` and `max` in the hashmap field
+    pub fn hashmap_rect(&self, min: Position, max: Position) -> usize {
+        self.hashmap.cells_in_rect(min, max).len()
+    }
+}
+
+/// A befunge93 interpreter that keeps stepping through a program
+pub struct Runner {
+    program: app::FungeSpace,
+    state: befunge93::State,
+    settings: Settings,
+}
+
+impl Runner {
+    /// Loads `program` with the default settings
+    pub fn new(program: &str) -> Self {
+        let program = app::FungeSpace::new_from_string(program, None);
+        Self {
+            state: befunge93::State::new_from_fungespace(program.clone()),
+            program,
+            settings: Settings::default(),
+        }
+    }
+
+    /// Turns all the position, `g`, `p` and changed cell tracking on or off
+    pub fn with_history(mut self, on: bool) -> Self {
+        self.settings.pos_history.0 = on;
+        self.settings.get_history.0 = on;
+        self.settings.put_history.0 = on;
+        self.settings.changed_cells.0 = on;
+        self
+    }
+
+    /// Takes `steps` steps, starting over whenever the program stops
+    pub fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            match self.state.step(&self.settings) {
+                StepStatus::Normal | StepStatus::NormalNoStep | StepStatus::SyncFrame => (),
+                StepStatus::Breakpoint | StepStatus::Error(_) => {
+                    self.state = befunge93::State::new_from_fungespace(self.program.clone());
+                }
+            }
+        }
+    }
+}
@@ -4,4 +4,7 @@ mod app;
 mod befunge;
 mod befunge93;
 mod befunge93mini;
+#[doc(hidden)]
+pub mod bench;
 pub use app::App;
+pub use befunge::run_to_completion;
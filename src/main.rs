@@ -6,6 +6,53 @@
 fn main() -> eframe::Result {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
 
+    let mut args = std::env::args().skip(1);
+    let (mut run, mut input) = (None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--run" | "--input" => {
+                let Some(path) = args.next() else {
+                    eprintln!("{arg} needs a file");
+                    std::process::exit(2);
+                };
+                if arg == "--run" {
+                    run = Some(path);
+                } else {
+                    input = Some(path);
+                }
+            }
+            _ => {
+                eprintln!("Unknown argument {arg:?}, expected --run <file> [--input <file>]");
+                std::process::exit(2);
+            }
+        }
+    }
+    // the editor has its own input box, so a file of input only means anything headlessly
+    if input.is_some() && run.is_none() {
+        eprintln!("--input only works with --run <file>");
+        std::process::exit(2);
+    }
+
+    // run headlessly, without ever opening a window
+    if let Some(path) = run {
+        let read = |path: &str| {
+            std::fs::read_to_string(path).unwrap_or_else(|err| {
+                eprintln!("Failed to read {path}: {err}");
+                std::process::exit(2);
+            })
+        };
+        let program = read(&path);
+        let input = input.as_deref().map(read).unwrap_or_default();
+        match befunge_editor::run_to_completion(&program, &input) {
+            Ok(output) => print!("{output}"),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([400.0, 300.0])